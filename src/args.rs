@@ -1,4 +1,13 @@
 use clap::Parser;
+use std::path::PathBuf;
+
+use crate::{
+    player_colors::{self, ColorTolerance},
+    probe::ProbeSampling,
+    protocol::Encoding,
+    trap::TrapPolicy,
+    viewport::Viewport,
+};
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
@@ -7,8 +16,113 @@ pub struct Args {
     #[clap(short, long, default_value = "[::1]:1234")]
     pub server_address: String,
 
+    /// Only play in this part of the screen, given as `<x>,<y>,<width>,<height>`, e.g. when the Pixelflut wall is
+    /// shared with other projects. The field is fitted into the viewport and the ball never leaves it.
+    /// If not specified, the whole screen is used.
+    #[clap(long)]
+    pub viewport: Option<Viewport>,
+
+    /// Field definition file, naming the images of the field and its hitbox as well as the positions of the goals,
+    /// scores and kick-off point
+    #[clap(long, default_value = "fields/default.toml")]
+    pub field: PathBuf,
+
     /// Frames per second the game should try to reach.
     /// This is mainly limited by the (network) latency to read the pixel values.
     #[clap(short, long, default_value = "20")]
     pub fps: u16,
+
+    /// Wire format used to set pixels. `binary` uses the `PB` command, which needs less than half the bandwidth,
+    /// but is not supported by all servers.
+    /// If not specified, the best encoding supported by the server is used.
+    #[clap(short, long, value_enum)]
+    pub encoding: Option<Encoding>,
+
+    /// Angle (in degrees) of the sector in front of the ball that is checked for collisions.
+    /// Smaller values need less pixel reads per tick, 360 checks the full ring around the ball.
+    #[clap(long, default_value = "180", value_parser = clap::value_parser!(u16).range(1..=360))]
+    pub probe_angle: u16,
+
+    /// Which pixels of the probed sector are read: `full`, `every-nth:<n>` or `rays:<n>`.
+    /// Sparse sampling needs less pixel reads per tick, which helps on high-latency servers.
+    #[clap(long, default_value_t = ProbeSampling::Full)]
+    pub probe_sampling: ProbeSampling,
+
+    /// Image of the ball
+    #[clap(long, default_value = "images/ball_v1.png")]
+    pub ball_image: String,
+
+    /// Pixels the ball moves per tick after kick-off
+    #[clap(long, default_value = "10")]
+    pub ball_speed: f32,
+
+    /// Minimum speed the ball slows down to because of friction. Defaults to `ball_speed`.
+    #[clap(long)]
+    pub ball_min_speed: Option<f32>,
+
+    /// Maximum speed the ball can be accelerated to by players. Defaults to `ball_speed`.
+    #[clap(long)]
+    pub ball_max_speed: Option<f32>,
+
+    /// Fraction of its speed the ball loses every tick
+    #[clap(long, default_value = "0")]
+    pub ball_friction: f32,
+
+    /// Factor the speed of the ball gets multiplied with when bouncing off a player
+    #[clap(long, default_value = "1")]
+    pub ball_hit_acceleration: f32,
+
+    /// Maximum number of pixels the ball moves between two collision checks. Faster balls check for collisions
+    /// multiple times per tick, so they can't glitch through thin lines. Must not be bigger than the radius of the
+    /// ball.
+    #[clap(long, default_value = "10")]
+    pub ball_max_step: f32,
+
+    /// Radius of the ball in pixels. Defaults to half of the width of the ball image.
+    #[clap(long)]
+    pub ball_radius: Option<f32>,
+
+    /// What to do with a ball that players trapped by painting over it: `teleport` (to the nearest free spot),
+    /// `pass-through:<ticks>` (ignore player pixels for the given number of ticks) or `reset` (back to kick-off)
+    #[clap(long, default_value_t = TrapPolicy::Teleport)]
+    pub trap_policy: TrapPolicy,
+
+    /// Number of consecutive ticks the ball needs to be surrounded by player pixels to be considered trapped
+    #[clap(long, default_value = "5")]
+    pub trap_ticks: u32,
+
+    /// Fraction of the pixels in the ring around the ball that need to be player pixels to count as trapped in a tick.
+    /// The whole ring is read for this, independent of the probe sector and sampling.
+    #[clap(long, default_value = "0.6")]
+    pub trap_ratio: f32,
+
+    /// Comma-separated colors (as hex rgb) of the players the ball bounces off, no matter which team they play for
+    #[clap(long, value_delimiter = ',', default_value = "ff0000", value_parser = player_colors::parse_color)]
+    pub player_colors: Vec<u32>,
+
+    /// Comma-separated colors (as hex rgb) only used by the team on the left side, so that their touches can be
+    /// attributed to them, e.g. `ff0000`
+    #[clap(long, value_delimiter = ',', value_parser = player_colors::parse_color)]
+    pub left_team_colors: Vec<u32>,
+
+    /// Comma-separated colors (as hex rgb) only used by the team on the right side, e.g. `0000ff`
+    #[clap(long, value_delimiter = ',', value_parser = player_colors::parse_color)]
+    pub right_team_colors: Vec<u32>,
+
+    /// How much a pixel may differ from the player colors to still count as player: `exact`, `rgb:<distance>`
+    /// (euclidean distance in RGB space) or `hsv:<hue>,<saturation>,<value>` (maximum difference of each component,
+    /// with the hue in degrees and the saturation and value from 0 to 1).
+    /// Anti-aliased drawings need a tolerance, but players should not be confused with the field or ball.
+    #[clap(long, default_value_t = ColorTolerance::Exact)]
+    pub color_tolerance: ColorTolerance,
+
+    /// Seed for all random decisions of the game, e.g. the kick-off directions.
+    /// A run with the same seed and the same input from the server behaves identically.
+    /// If not specified, a random seed is picked and printed on startup.
+    #[clap(long)]
+    pub seed: Option<u64>,
+
+    /// Number of balls in the game at the same time
+    #[clap(long, default_value = "1", value_parser = clap::value_parser!(u16).range(1..))]
+    pub balls: u16,
 }
@@ -1,228 +1,1002 @@
 use async_trait::async_trait;
 use atomic_float::AtomicF32;
-use image::{io::Reader as ImageReader, DynamicImage, GenericImageView};
-use rand::{prelude::SliceRandom, thread_rng, Rng};
+use image::{imageops::FilterType, io::Reader as ImageReader, DynamicImage};
+use rand::{prelude::SliceRandom, rngs::StdRng, Rng};
 use std::{
+    collections::VecDeque,
     f32::consts::PI,
-    io::Result,
-    sync::atomic::Ordering::{Acquire, Release},
+    io::{self, Error, ErrorKind},
+    sync::{
+        atomic::{
+            AtomicU32,
+            Ordering::{AcqRel, Acquire, Release},
+        },
+        Arc,
+    },
 };
-use tokio::sync::RwLock;
+use tokio::sync::{Mutex, RwLock};
 
 use crate::{
-    client::{Client, AVG_BYES_PER_PIXEL_SET_COMMAND},
+    canvas::{self, Canvas, PendingDonut, FIELD_HITBOX_COLOR},
+    client,
     draw::Draw,
-    game::GoalScored,
-    image_helpers::{self, get_donut_coordinates, RED},
-    protocol::Serialize,
+    events::{Events, GameEvent},
+    field_layout::FieldLayout,
+    game::{Goal, GoalScored, Team},
+    image_helpers::{self, get_donut_coordinates},
+    player_colors::{Player, PlayerColors},
+    probe::ProbeConfig,
+    protocol::{Encoding, PixelflutRequest, ProtocolError, Serialize},
+    trap::{self, TrapConfig, TrapPolicy},
+    viewport::Viewport,
 };
 
-pub const TARGET_COLOR: u32 = RED;
+/// How the speed (in pixels per tick) of the ball changes over time
+#[derive(Clone, Copy, Debug)]
+pub struct BallPhysics {
+    /// Speed after kick-off
+    pub speed: f32,
+    pub min_speed: f32,
+    pub max_speed: f32,
+    /// Fraction of the speed that is lost every tick, until `min_speed` is reached
+    pub friction: f32,
+    /// The speed gets multiplied by this factor when the ball bounces off a player, up to `max_speed`
+    pub hit_acceleration: f32,
+    /// Maximum distance the ball moves between two collision checks. If the ball is faster, a tick is split into
+    /// multiple sub-steps, each checking for collisions.
+    pub max_step: f32,
+}
 
-// ####################
-// Be careful about changing any of these constants below!
-// They can change the way the ball behaves, in the worst case letting it glitch through walls or bounce in the wrong direction
-// ####################
-const SPEED: f32 = 10.0_f32;
-// Measure the following variables with an image editing program
-const BALL_IMAGE_SIZE: u16 = 80; // Assuming quadratic image this is the width and height of the image
-const BALL_RADIUS: f32 = 40_f32;
+/// Be careful about changing the speed or radius!
+/// They can change the way the ball behaves, in the worst case letting it glitch through walls or bounce in the wrong
+/// direction. [`BallConfig::new`] rejects combinations known to be problematic.
+pub struct BallConfig {
+    image: DynamicImage,
+    radius: f32,
+    physics: BallPhysics,
+    probe: ProbeConfig,
+    trap: TrapConfig,
+    colors: PlayerColors,
+}
+
+impl BallConfig {
+    /// Loads the sprite of the ball from `image_path`.
+    /// If no `radius` is given, half of the width of the sprite is used.
+    pub fn new(
+        image_path: &str,
+        radius: Option<f32>,
+        physics: BallPhysics,
+        probe: ProbeConfig,
+        trap: TrapConfig,
+        colors: PlayerColors,
+    ) -> io::Result<Self> {
+        let image = ImageReader::open(image_path)?.decode().map_err(|err| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("Failed to decode ball image {image_path}: {err}"),
+            )
+        })?;
+        if image.width() != image.height() {
+            println!(
+                "WARNING: The ball image {image_path} is not quadratic ({}x{}), only its width is used",
+                image.width(),
+                image.height()
+            );
+        }
+        let image_size = image.width() as u16;
+        let radius = radius.unwrap_or(image_size as f32 / 2.0);
+
+        let BallPhysics {
+            speed,
+            min_speed,
+            max_speed,
+            friction,
+            hit_acceleration,
+            max_step,
+        } = physics;
+        if min_speed.is_nan() || min_speed <= 0.0 {
+            return Err(invalid_config(format!(
+                "The minimum ball speed must be positive, but is {min_speed}"
+            )));
+        }
+        if !(min_speed..=max_speed).contains(&speed) {
+            return Err(invalid_config(format!(
+                "The ball speed ({speed}) must be between the minimum ({min_speed}) and maximum speed ({max_speed})"
+            )));
+        }
+        if radius.is_nan() || radius <= 0.0 {
+            return Err(invalid_config(format!(
+                "The ball radius must be positive, but is {radius}"
+            )));
+        }
+        if max_step.is_nan() || max_step <= 0.0 {
+            return Err(invalid_config(format!(
+                "The maximum ball step must be positive, but is {max_step}"
+            )));
+        }
+        // Collisions are searched for in a band of radius ± step/2 around the ball center. If the band is wider
+        // than the ball itself, the nearest red pixel can be inside the ball, letting it bounce in the wrong direction
+        // or glitch through walls.
+        if max_step > radius {
+            return Err(invalid_config(format!(
+                "The maximum ball step ({max_step}) must not be bigger than its radius ({radius}), as otherwise it can glitch through walls"
+            )));
+        }
+        if !(0.0..1.0).contains(&friction) {
+            return Err(invalid_config(format!(
+                "The ball friction must be at least 0 and less than 1, but is {friction}"
+            )));
+        }
+        if trap.ticks == 0 {
+            return Err(invalid_config(
+                "The number of trap ticks must be at least 1".to_string(),
+            ));
+        }
+        if !(trap.ratio > 0.0 && trap.ratio <= 1.0) {
+            return Err(invalid_config(format!(
+                "The trap ratio must be greater than 0 and at most 1, but is {}",
+                trap.ratio
+            )));
+        }
+        if hit_acceleration.is_nan() || hit_acceleration <= 0.0 {
+            return Err(invalid_config(format!(
+                "The ball hit acceleration must be positive, but is {hit_acceleration}"
+            )));
+        }
+
+        Ok(BallConfig {
+            image,
+            radius,
+            physics,
+            probe,
+            trap,
+            colors,
+        })
+    }
+
+    /// Sprite of the ball
+    pub fn image(&self) -> &DynamicImage {
+        &self.image
+    }
+
+    pub fn colors(&self) -> &PlayerColors {
+        &self.colors
+    }
+
+    /// Radius used for collisions
+    pub fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    /// Scales the ball together with the field (see [`FieldLayout::fit_to_screen`]), so that it keeps its size and
+    /// speed relative to the field. The ratios checked by [`BallConfig::new`] stay the same.
+    pub fn scale(self, scale: f32) -> Self {
+        if scale == 1.0 {
+            return self;
+        }
+
+        let image_size = ((self.image.width() as f32 * scale).round() as u32).max(1);
+        let image_height = ((self.image.height() as f32 * scale).round() as u32).max(1);
+        let physics = BallPhysics {
+            speed: self.physics.speed * scale,
+            min_speed: self.physics.min_speed * scale,
+            max_speed: self.physics.max_speed * scale,
+            max_step: self.physics.max_step * scale,
+            ..self.physics
+        };
+        BallConfig {
+            image: self
+                .image
+                .resize_exact(image_size, image_height, FilterType::Triangle),
+            radius: self.radius * scale,
+            physics,
+            ..self
+        }
+    }
+}
+
+fn invalid_config(message: String) -> Error {
+    Error::new(ErrorKind::InvalidInput, message)
+}
+
+/// The ball bounced off a player
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Touch {
+    /// Color of the player pixel nearest to the ball
+    pub rgb: u32,
+    pub player: Player,
+}
+
+impl Touch {
+    pub fn team(&self) -> Option<Team> {
+        match self.player {
+            Player::Anyone => None,
+            Player::Team(team) => Some(team),
+        }
+    }
+}
+
+/// Result of a collision of the ball
+struct Bounce {
+    /// Direction the ball moves into after the bounce
+    dir: f32,
+    /// Set if the ball bounced off player pixels, rather than an edge of the screen or the field hitbox
+    with_player: Option<Touch>,
+    /// Movement needed to get the ball out of the pixels it bounced off
+    push_x: f32,
+    push_y: f32,
+}
 
 pub struct Ball {
-    image: DynamicImage,
+    /// Index of the ball in the game, used to tell the balls apart in the events
+    id: usize,
+    config: Arc<BallConfig>,
     draw_command_bytes: RwLock<Vec<u8>>,
+    /// Only set if the server supports the `OFFSET` command.
+    /// Contains the draw commands of the ball relative to (0,0), so that they only need to be calculated once.
+    /// For drawing an `OFFSET` command with the current ball position is sent in front of them.
+    offset_draw_command_bytes: Option<Vec<u8>>,
 
-    field_hitbox_image: DynamicImage,
+    field: Arc<FieldLayout>,
+    /// Where the ball is put on reset. Every ball has its own, so that they don't start on top of each other.
+    kick_off: (f32, f32),
+    /// Pixel reads for the next tick, sent at the end of the current one
+    next_probe: Mutex<Option<PendingDonut>>,
 
     center_x: AtomicF32,
     center_y: AtomicF32,
     dir: AtomicF32,
-
-    screen_width: u16,
-    screen_height: u16,
+    /// Pixels the ball moves per tick
+    speed: AtomicF32,
+    /// Number of consecutive ticks the ball was surrounded by player pixels
+    trapped_ticks: AtomicU32,
+    /// While greater than zero the ball ignores player pixels, counting down every tick
+    pass_through_ticks: AtomicU32,
+    /// Seeded by the game, so that the kick-off directions can be reproduced
+    rng: std::sync::Mutex<StdRng>,
+    /// The last player the ball bounced off since the kick-off
+    last_touch: std::sync::Mutex<Option<Touch>>,
+    /// The touch before the last one, see [`GoalScored::assist`]
+    previous_touch: std::sync::Mutex<Option<Touch>>,
+    /// The player the ball bounced off in the previous tick, if any. A ball rolling along a player bounces off it in
+    /// many consecutive ticks, which only counts as one touch.
+    previous_contact: std::sync::Mutex<Option<Player>>,
+
+    /// All coordinates of the ball are relative to the viewport, only the draw commands are translated to the screen
+    viewport: Viewport,
+
+    encoding: Encoding,
+    events: Events,
 }
 
 impl Ball {
-    pub async fn new(screen_width: u16, screen_height: u16) -> Result<Self> {
-        let image = ImageReader::open("images/ball_v1.png")?
-            .decode()
-            .expect("Failed to decode ball image");
-
-        let field_hitbox_image = ImageReader::open("images/field_v3_hitbox.png")?
-            .decode()
-            .expect("Failed to decode field hitbox image");
+    #[allow(clippy::too_many_arguments)]
+    pub async fn new(
+        id: usize,
+        viewport: Viewport,
+        encoding: Encoding,
+        use_offset: bool,
+        config: Arc<BallConfig>,
+        field: Arc<FieldLayout>,
+        kick_off: (f32, f32),
+        mut rng: StdRng,
+        events: Events,
+    ) -> io::Result<Self> {
+        let offset_draw_command_bytes = use_offset.then(|| {
+            let mut draw_commands = image_helpers::draw_image(&config.image, 0, 0);
+            // Shuffle commands to prevent drawing artefacts
+            draw_commands.shuffle(&mut rng);
+            client::commands_to_bytes(&draw_commands, encoding)
+        });
 
         let ball = Ball {
-            image,
+            id,
+            config,
             draw_command_bytes: RwLock::new(vec![]),
-            field_hitbox_image,
+            offset_draw_command_bytes,
+            field,
+            kick_off,
+            next_probe: Mutex::new(None),
             // The following values are irrelevant as the ball will be reset after creation
             center_x: AtomicF32::new(0.0),
             center_y: AtomicF32::new(0.0),
             dir: AtomicF32::new(0.0),
-            screen_width,
-            screen_height,
+            speed: AtomicF32::new(0.0),
+            trapped_ticks: AtomicU32::new(0),
+            pass_through_ticks: AtomicU32::new(0),
+            rng: std::sync::Mutex::new(rng),
+            last_touch: std::sync::Mutex::new(None),
+            previous_touch: std::sync::Mutex::new(None),
+            previous_contact: std::sync::Mutex::new(None),
+            viewport,
+            encoding,
+            events,
         };
         ball.reset();
-        ball.update_draw_command_bytes().await;
+        if ball.offset_draw_command_bytes.is_none() {
+            ball.update_draw_command_bytes().await;
+        }
 
         Ok(ball)
     }
 
-    async fn update_draw_command_bytes(&self) {
-        let mut draw_commands = image_helpers::draw_image(
-            &self.image,
-            (self.center_x.load(Acquire) - BALL_RADIUS) as u16,
-            (self.center_y.load(Acquire) - BALL_RADIUS) as u16,
+    /// Top left corner of the ball image on the screen. The image is centered on the ball, independent of the radius
+    /// used for collisions, but never leaves the viewport.
+    fn image_position(&self) -> (u16, u16) {
+        let (width, height) = (
+            self.config.image.width() as f32,
+            self.config.image.height() as f32,
         );
+        let x = (self.center_x.load(Acquire) - width / 2.0)
+            .clamp(0.0, (self.viewport.width as f32 - width).max(0.0));
+        let y = (self.center_y.load(Acquire) - height / 2.0)
+            .clamp(0.0, (self.viewport.height as f32 - height).max(0.0));
+        self.viewport.to_screen(x as u16, y as u16)
+    }
+
+    async fn update_draw_command_bytes(&self) {
+        let (x, y) = self.image_position();
+        let mut draw_commands = image_helpers::draw_image(&self.config.image, x, y);
 
         // Shuffle commands to prevent drawing artefacts
-        draw_commands.shuffle(&mut thread_rng());
+        draw_commands.shuffle(&mut *self.rng.lock().unwrap());
+
+        *(self.draw_command_bytes.write().await) =
+            client::commands_to_bytes(&draw_commands, self.encoding);
+    }
+
+    /// Moves the ball by one tick. Returns the touch of the player that kicked the ball in this tick (the last one it
+    /// bounced off), if any. A player the ball already bounced off in the previous tick is still in contact with it, so
+    /// this is not returned as a new touch.
+    pub async fn tick<C: Canvas>(&self, canvas: &mut C) -> Result<Option<Touch>, ProtocolError> {
+        let speed = self.speed.load(Acquire);
+        let (steps, step_length) = self.sub_steps(speed);
+
+        let mut center_x = self.center_x.load(Acquire);
+        let mut center_y = self.center_y.load(Acquire);
+        let mut dir = self.dir.load(Acquire);
+        let mut kicked_by = None;
+        let passing_through = self.pass_through_ticks.load(Acquire) > 0;
+        // Highest fraction of player pixels seen in the probes of this tick. The probes only cover the direction of
+        // travel, so this is just a hint that the ball might be trapped, see `ring_player_ratio`.
+        let mut trapped_ratio: f32 = 0.0;
+
+        // Instead of jumping the whole distance at once (possibly skipping thin lines), we move in sub-steps that are
+        // small enough to not miss anything, bouncing at the first contact.
+        // The probes of all sub-steps are requested at once, assuming the ball moves in a straight line. Only when
+        // the ball bounces the remaining probes are thrown away and requested again for the new direction.
+        let mut probes = VecDeque::new();
+        for step in 0..steps {
+            if probes.is_empty() {
+                probes = self
+                    .request_probes(canvas, center_x, center_y, dir, step_length, steps - step)
+                    .await?;
+            }
+
+            let probe = probes
+                .pop_front()
+                .expect("There must be a probe for every remaining sub-step");
+            let probed_pixels = probe.len();
+            let donut = match canvas.receive_screen_donut(probe).await {
+                Ok(donut) => donut,
+                Err(err) => {
+                    // Keep the connection usable by reading the responses we still expect
+                    for probe in probes {
+                        let _ = canvas.discard_screen_donut(probe).await;
+                    }
+                    return Err(err);
+                }
+            };
+
+            if probed_pixels > 0 {
+                let player_pixels = donut
+                    .iter()
+                    .flatten()
+                    .filter(|rgb| self.config.colors.classify(**rgb).is_some())
+                    .count();
+                trapped_ratio = trapped_ratio.max(player_pixels as f32 / probed_pixels as f32);
+            }
+
+            if let Some(bounce) = self.collide(
+                center_x,
+                center_y,
+                dir,
+                step_length,
+                &donut,
+                passing_through,
+            ) {
+                dir = bounce.dir;
+                kicked_by = bounce.with_player.or(kicked_by);
+                self.events.publish(GameEvent::Bounce {
+                    ball: self.id,
+                    touch: bounce.with_player,
+                });
+                center_x += bounce.push_x;
+                center_y += bounce.push_y;
+                for probe in probes.drain(..) {
+                    canvas.discard_screen_donut(probe).await?;
+                }
+            }
+
+            center_x += step_length * dir.cos();
+            center_y += step_length * dir.sin();
+        }
+        // The edges are only checked before moving, so the last sub-step can end past them
+        let (center_x, center_y) = self.clamp_to_screen(center_x, center_y);
+
+        let physics = &self.config.physics;
+        // Players kick the ball, walls don't
+        let next_speed = if kicked_by.is_some() {
+            (speed * physics.hit_acceleration).clamp(physics.min_speed, physics.max_speed)
+        } else {
+            (speed * (1.0 - physics.friction)).max(physics.min_speed)
+        };
+
+        self.center_x.store(center_x, Release);
+        self.center_y.store(center_y, Release);
+        self.dir.store(dir, Release);
+        self.speed.store(next_speed, Release);
+        let previous_contact = std::mem::replace(
+            &mut *self.previous_contact.lock().unwrap(),
+            kicked_by.map(|touch| touch.player),
+        );
+        let new_touch = kicked_by.filter(|touch| previous_contact != Some(touch.player));
+        if let Some(touch) = new_touch {
+            let mut last_touch = self.last_touch.lock().unwrap();
+            *self.previous_touch.lock().unwrap() = last_touch.replace(touch);
+        }
+
+        // A ball moving head-on into a big drawing sees a lot of player pixels ahead of it, but it is only trapped if they
+        // are all around it. Reading the whole ring is expensive, so it's only done when the probes hint at a trap.
+        if passing_through {
+            self.pass_through_ticks.fetch_sub(1, AcqRel);
+        } else if trapped_ratio >= self.config.trap.ratio
+            && self.ring_player_ratio(canvas, center_x, center_y).await? >= self.config.trap.ratio
+        {
+            if self.trapped_ticks.fetch_add(1, AcqRel) + 1 >= self.config.trap.ticks {
+                self.trapped_ticks.store(0, Release);
+                self.escape_trap(canvas).await?;
+            }
+        } else {
+            self.trapped_ticks.store(0, Release);
+        }
+
+        // Already send the reads for the next tick, so that the responses are (hopefully) there once we need them.
+        // Escaping a trap might have moved the ball, so we need to load the current state again.
+        let (_, next_step_length) = self.sub_steps(self.speed.load(Acquire));
+        let mut next_probes = self
+            .request_probes(
+                canvas,
+                self.center_x.load(Acquire),
+                self.center_y.load(Acquire),
+                self.dir.load(Acquire),
+                next_step_length,
+                1,
+            )
+            .await?;
+        *self.next_probe.lock().await = next_probes.pop_front();
+
+        // When using OFFSET the draw commands don't depend on the position of the ball
+        if self.offset_draw_command_bytes.is_none() {
+            self.update_draw_command_bytes().await;
+        }
+
+        Ok(new_touch)
+    }
+
+    /// Returns the fraction of player pixels in the whole contact band around the given center, no matter into which
+    /// direction the ball moves or how the probes are sampled
+    async fn ring_player_ratio<C: Canvas>(
+        &self,
+        canvas: &mut C,
+        center_x: f32,
+        center_y: f32,
+    ) -> Result<f32, ProtocolError> {
+        let half_step = self.config.physics.max_step / 2.0;
+        let (x_center, y_center) = (center_x as i16, center_y as i16);
+        let outer_circle_radius = self.config.radius + half_step;
+        let coordinates = get_donut_coordinates(
+            x_center,
+            y_center,
+            self.config.radius - half_step,
+            outer_circle_radius,
+            self.viewport.width,
+            self.viewport.height,
+        );
+        let ring_pixels = coordinates.len();
+        if ring_pixels == 0 {
+            return Ok(0.0);
+        }
 
-        let mut draw_command_bytes =
-            Vec::with_capacity(draw_commands.len() * AVG_BYES_PER_PIXEL_SET_COMMAND);
-        draw_commands.iter().for_each(|cmd| {
-            cmd.serialize(&mut draw_command_bytes);
+        let pending = canvas
+            .request_screen_pixels(
+                x_center,
+                y_center,
+                outer_circle_radius,
+                coordinates,
+                Some(&self.field.hitbox),
+            )
+            .await?;
+        let player_pixels = canvas
+            .receive_screen_donut(pending)
+            .await?
+            .iter()
+            .flatten()
+            .filter(|rgb| self.config.colors.classify(**rgb).is_some())
+            .count();
+        Ok(player_pixels as f32 / ring_pixels as f32)
+    }
+
+    /// Frees a ball that got trapped by players painting over it, according to the configured policy
+    async fn escape_trap<C: Canvas>(&self, canvas: &mut C) -> Result<(), ProtocolError> {
+        let policy = self.config.trap.policy;
+        self.events.publish(GameEvent::Trapped {
+            ball: self.id,
+            policy,
         });
 
-        *(self.draw_command_bytes.write().await) = draw_command_bytes;
+        match policy {
+            TrapPolicy::Teleport => {
+                let free_spot = self
+                    .find_free_spot(
+                        canvas,
+                        self.center_x.load(Acquire),
+                        self.center_y.load(Acquire),
+                    )
+                    .await?;
+                match free_spot {
+                    Some((x, y)) => {
+                        self.center_x.store(x, Release);
+                        self.center_y.store(y, Release);
+                    }
+                    // The players painted everything around the ball
+                    None => self.reset(),
+                }
+            }
+            TrapPolicy::PassThrough(ticks) => self.pass_through_ticks.store(ticks, Release),
+            TrapPolicy::Reset => self.reset(),
+        }
+
+        Ok(())
     }
 
-    pub async fn tick(&self, client: &mut Client) -> Result<()> {
-        let dir = self.dir.load(Acquire);
-        let center_x = self.center_x.load(Acquire);
-        let center_y = self.center_y.load(Acquire);
-        let mut movement_x = SPEED * self.dir.load(Acquire).cos();
-        let mut movement_y = SPEED * self.dir.load(Acquire).sin();
+    /// Returns the nearest center around the given one where the ball does not touch any players or walls
+    async fn find_free_spot<C: Canvas>(
+        &self,
+        canvas: &mut C,
+        center_x: f32,
+        center_y: f32,
+    ) -> Result<Option<(f32, f32)>, ProtocolError> {
+        let radius = self.config.radius;
+        let candidate_groups = trap::escape_candidates(
+            center_x,
+            center_y,
+            radius,
+            self.viewport.width,
+            self.viewport.height,
+        );
+
+        for candidates in candidate_groups {
+            // Check all candidates with the same distance at once, to only pay the latency once
+            let mut pending = Vec::with_capacity(candidates.len());
+            for (x, y) in &candidates {
+                let coordinates = trap::free_spot_coordinates(
+                    *x as i16,
+                    *y as i16,
+                    radius,
+                    self.viewport.width,
+                    self.viewport.height,
+                );
+                pending.push(
+                    canvas
+                        .request_screen_pixels(
+                            *x as i16,
+                            *y as i16,
+                            radius + 1.0,
+                            coordinates,
+                            Some(&self.field.hitbox),
+                        )
+                        .await?,
+                );
+            }
+
+            // All responses need to be read, even if we already found a free spot
+            let mut free_spot = None;
+            let mut first_error = None;
+            for (candidate, pending) in candidates.into_iter().zip(pending) {
+                match canvas.receive_screen_donut(pending).await {
+                    Ok(donut) => {
+                        let is_free = !donut.iter().flatten().any(|rgb| {
+                            *rgb == FIELD_HITBOX_COLOR
+                                || self.config.colors.classify(*rgb).is_some()
+                        });
+                        if is_free && free_spot.is_none() {
+                            free_spot = Some(candidate);
+                        }
+                    }
+                    Err(err) if err.is_connection_error() => return Err(err),
+                    Err(err) => {
+                        first_error.get_or_insert(err);
+                    }
+                }
+            }
+
+            if let Some(err) = first_error {
+                return Err(err);
+            }
+            if free_spot.is_some() {
+                return Ok(free_spot);
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Splits the movement of a tick into sub-steps no longer than the configured maximum step.
+    /// Returns the number of sub-steps and the length of each of them.
+    fn sub_steps(&self, speed: f32) -> (usize, f32) {
+        let steps = (speed / self.config.physics.max_step).ceil().max(1.0);
+        (steps as usize, speed / steps)
+    }
+
+    /// Checks if the ball collides with an edge of the screen or with the players and walls in the probe when moving
+    /// `step_length` pixels into `dir`. Returns how the ball bounces, if at all.
+    /// The ball is reflected about the surface normal estimated from all player and wall pixels it touches.
+    /// If `ignore_players` is set, only the edges of the screen and the field hitbox are solid.
+    fn collide(
+        &self,
+        center_x: f32,
+        center_y: f32,
+        dir: f32,
+        step_length: f32,
+        donut: &[Vec<u32>],
+        ignore_players: bool,
+    ) -> Option<Bounce> {
+        let radius = self.config.radius;
+        let mut movement_x = dir.cos();
+        let mut movement_y = dir.sin();
 
         let mut bounced_with_edge = false;
 
-        // Collision on left or right
-        if center_x - BALL_RADIUS <= 0_f32 || center_x + BALL_RADIUS >= self.screen_width as f32 {
+        // Collision on left or right. Only bounce when moving towards the edge, otherwise a ball that went too far
+        // would be flipped back and forth.
+        if (center_x - radius <= 0_f32 && movement_x < 0.0)
+            || (center_x + radius >= self.viewport.width as f32 && movement_x > 0.0)
+        {
             movement_x *= -1_f32;
             bounced_with_edge = true;
         }
 
         // Collision on top or bottom
-        if center_y - BALL_RADIUS <= 0_f32 || center_y + BALL_RADIUS >= self.screen_height as f32 {
+        if (center_y - radius <= 0_f32 && movement_y < 0.0)
+            || (center_y + radius >= self.viewport.height as f32 && movement_y > 0.0)
+        {
             movement_y *= -1_f32;
             bounced_with_edge = true;
         }
 
-        let inner_circle_radius = BALL_RADIUS - SPEED / 2.0;
-        let outer_circle_radius = BALL_RADIUS + SPEED / 2.0;
+        if bounced_with_edge {
+            return Some(Bounce {
+                dir: movement_y.atan2(movement_x),
+                with_player: None,
+                push_x: 0.0,
+                push_y: 0.0,
+            });
+        }
 
-        let donut = client
-            .get_screen_donut(
-                center_x as i16,
-                center_y as i16,
-                inner_circle_radius,
-                outer_circle_radius,
-                self.screen_width,
-                self.screen_height,
-                Some(&self.field_hitbox_image),
-            )
-            .await?;
+        let inner_circle_radius = radius - step_length / 2.0;
+        let outer_circle_radius = radius + step_length / 2.0;
 
-        let mut contains_target_color = false;
-        let mut min_x_value = 0.0;
-        let mut min_y_value = 0.0;
+        // Instead of only looking at the nearest red pixel, we take all red pixels in the contact band into account to
+        // get a stable surface normal, even for rough player drawings and corners
+        let mut contact_count = 0;
+        let mut contact_sum_x = 0.0;
+        let mut contact_sum_y = 0.0;
         let mut min_distance = f32::MAX;
-
-        for x in 0..donut.len() {
-            for y in 0..donut[0].len() {
-                if donut[x][y] == TARGET_COLOR {
-                    contains_target_color = true;
-                    let x_rel = x as f32 - outer_circle_radius;
-                    let y_rel = y as f32 - outer_circle_radius;
+        let mut nearest_touch = None;
+        // The probe is indexed relative to the truncated radius, see `Canvas::request_screen_pixels`
+        let grid_offset = outer_circle_radius as i16 as f32;
+
+        for (x, column) in donut.iter().enumerate() {
+            for (y, rgb) in column.iter().enumerate() {
+                let player = match ignore_players {
+                    true => None,
+                    false => self.config.colors.classify(*rgb),
+                };
+                if player.is_some() || *rgb == FIELD_HITBOX_COLOR {
+                    let x_rel = x as f32 - grid_offset;
+                    let y_rel = y as f32 - grid_offset;
                     let distance = f32::sqrt(f32::powi(x_rel, 2) + f32::powi(y_rel, 2));
+                    if !(inner_circle_radius..=outer_circle_radius).contains(&distance) {
+                        continue;
+                    }
+
+                    contact_count += 1;
+                    contact_sum_x += x_rel / distance;
+                    contact_sum_y += y_rel / distance;
                     if distance < min_distance {
                         min_distance = distance;
-                        min_x_value = x_rel;
-                        min_y_value = y_rel;
+                        nearest_touch = player.map(|player| Touch { rgb: *rgb, player });
                     }
                 }
             }
         }
 
-        if !bounced_with_edge
-            && contains_target_color
-            && min_distance <= BALL_RADIUS + SPEED / 2.0
-            && min_distance >= BALL_RADIUS - SPEED / 2.0
-        {
-            // Calculate direction to nearest red point
-            let nearest_red_dir = min_y_value.atan2(min_x_value);
-            let nearest_red_dir_reflect_vector = nearest_red_dir + PI;
-
-            // And the new direction to go after bounce
-            let bounce_dir =
-                nearest_red_dir_reflect_vector - (dir + PI - nearest_red_dir_reflect_vector);
+        if contact_count == 0 {
+            return None;
+        }
 
-            movement_x = SPEED * bounce_dir.cos();
-            movement_y = SPEED * bounce_dir.sin();
+        // The normal points from the contact pixels towards the center of the ball
+        let contact_length = f32::sqrt(contact_sum_x.powi(2) + contact_sum_y.powi(2));
+        if contact_length == 0.0 {
+            // Contacts on exactly opposite sides cancel each other out, there is no sensible direction to bounce off
+            return None;
+        }
+        let normal_x = -contact_sum_x / contact_length;
+        let normal_y = -contact_sum_y / contact_length;
 
-            // println!("BOUNCE: dir {dir} nearest_red_dir_reflect_vector: {nearest_red_dir_reflect_vector} bounce_dir {bounce_dir}");
+        // Only reflect when moving towards the surface, otherwise the ball is already moving away from it
+        let dot = movement_x * normal_x + movement_y * normal_y;
+        if dot >= 0.0 {
+            return None;
         }
+        let bounce_x = movement_x - 2.0 * dot * normal_x;
+        let bounce_y = movement_y - 2.0 * dot * normal_y;
+
+        // Push the ball out of the red pixels, so that it does not get stuck inside of them
+        let penetration = (radius - min_distance).max(0.0);
+
+        Some(Bounce {
+            dir: bounce_y.atan2(bounce_x),
+            with_player: nearest_touch,
+            push_x: penetration * normal_x,
+            push_y: penetration * normal_y,
+        })
+    }
 
-        self.center_x.store(center_x + movement_x, Release);
-        self.center_y.store(center_y + movement_y, Release);
-        self.dir.store(movement_y.atan2(movement_x), Release);
+    /// The pixels that need to be checked for collisions. Only the sector facing the direction of travel is relevant,
+    /// as the ball can only bounce off things it moves towards.
+    fn probe_coordinates(
+        &self,
+        x_center: i16,
+        y_center: i16,
+        dir: f32,
+        inner_circle_radius: f32,
+        outer_circle_radius: f32,
+    ) -> Vec<(u16, u16)> {
+        self.config.probe.coordinates(
+            x_center,
+            y_center,
+            inner_circle_radius,
+            outer_circle_radius,
+            dir,
+            self.viewport.width,
+            self.viewport.height,
+        )
+    }
 
-        self.update_draw_command_bytes().await;
+    /// Requests the collision probes for the next `steps` sub-steps of `step_length` pixels, assuming the ball moves in
+    /// a straight line into `dir`. For the first sub-step the reads sent at the end of the previous tick are used, if
+    /// they match.
+    #[allow(clippy::too_many_arguments)]
+    async fn request_probes<C: Canvas>(
+        &self,
+        canvas: &mut C,
+        center_x: f32,
+        center_y: f32,
+        dir: f32,
+        step_length: f32,
+        steps: usize,
+    ) -> Result<VecDeque<PendingDonut>, ProtocolError> {
+        let inner_circle_radius = self.config.radius - step_length / 2.0;
+        let outer_circle_radius = self.config.radius + step_length / 2.0;
+
+        let mut probes = VecDeque::with_capacity(steps);
+        for step in 0..steps {
+            let x_center = (center_x + step as f32 * step_length * dir.cos()) as i16;
+            let y_center = (center_y + step as f32 * step_length * dir.sin()) as i16;
+            let coordinates = self.probe_coordinates(
+                x_center,
+                y_center,
+                dir,
+                inner_circle_radius,
+                outer_circle_radius,
+            );
+
+            // The reads from the previous tick were sent first, so they must be handled before sending new ones
+            if let Some(prefetched) = self.next_probe.lock().await.take() {
+                if canvas.is_current(&prefetched)
+                    && prefetched.matches(x_center, y_center, outer_circle_radius, &coordinates)
+                {
+                    probes.push_back(prefetched);
+                    continue;
+                }
+                // E.g. the ball was reset since the reads were sent
+                canvas.discard_screen_donut(prefetched).await?;
+            }
 
-        Ok(())
+            probes.push_back(
+                canvas
+                    .request_screen_pixels(
+                        x_center,
+                        y_center,
+                        outer_circle_radius,
+                        coordinates,
+                        Some(&self.field.hitbox),
+                    )
+                    .await?,
+            );
+        }
+
+        Ok(probes)
+    }
+
+    /// The last player the ball bounced off since the kick-off
+    pub fn last_touch(&self) -> Option<Touch> {
+        *self.last_touch.lock().unwrap()
     }
 
-    /// Returns true if right team shot a goal, false if the left team shot a goal
-    /// and None if no goal was scored
+    /// Returns which goal the ball touches, if any, crediting the goal based on the last touch
     pub fn is_goal_scored(&self) -> Option<GoalScored> {
-        let center_x = self.center_x.load(Acquire) as i16;
-        let center_y = self.center_y.load(Acquire) as i16;
+        let assist = *self.previous_touch.lock().unwrap();
+        let center_x = self.center_x.load(Acquire);
+        let center_y = self.center_y.load(Acquire);
+        let radius = self.config.radius;
 
-        // BALL_RADIUS - SPEED should be sufficient but better safe than sorry.
-        // This operation is way cheaper than asking for pixels over the network.
-        let inner_circle_radius = BALL_RADIUS - SPEED * 2.0;
-        let outer_circle_radius = BALL_RADIUS;
+        if self
+            .field
+            .left
+            .goal
+            .intersects_circle(center_x, center_y, radius)
+        {
+            Some(GoalScored::new(Goal::Left, self.last_touch(), assist))
+        } else if self
+            .field
+            .right
+            .goal
+            .intersects_circle(center_x, center_y, radius)
+        {
+            Some(GoalScored::new(Goal::Right, self.last_touch(), assist))
+        } else {
+            None
+        }
+    }
 
-        let donut_coordinates = get_donut_coordinates(
-            center_x,
-            center_y,
-            inner_circle_radius,
-            outer_circle_radius,
-            self.screen_width,
-            self.screen_height,
-        );
+    pub fn center(&self) -> (f32, f32) {
+        (self.center_x.load(Acquire), self.center_y.load(Acquire))
+    }
 
-        for (x, y) in donut_coordinates {
-            let value = self.field_hitbox_image.get_pixel(x as u32, y as u32).0;
-            if value[0] == 0 && value[1] == 0 && value[2] == 255 && value[3] != 0 {
-                if center_x > self.screen_width as i16 / 2 {
-                    return Some(GoalScored::Right);
-                } else {
-                    return Some(GoalScored::Left);
-                }
-            }
+    /// Moves the ball without checking for collisions, e.g. to set up a specific situation
+    pub fn set_center(&self, x: f32, y: f32) {
+        self.center_x.store(x, Release);
+        self.center_y.store(y, Release);
+    }
+
+    /// Velocity in pixels per tick, split into x and y
+    pub fn velocity(&self) -> (f32, f32) {
+        let speed = self.speed.load(Acquire);
+        let dir = self.dir.load(Acquire);
+        (speed * dir.cos(), speed * dir.sin())
+    }
+
+    /// The speed is kept within the configured bounds. A velocity of zero has no direction, so the ball keeps moving
+    /// into its previous one.
+    pub fn set_velocity(&self, velocity_x: f32, velocity_y: f32) {
+        let physics = &self.config.physics;
+        let speed = f32::sqrt(velocity_x.powi(2) + velocity_y.powi(2));
+        if speed > 0.0 {
+            self.dir.store(velocity_y.atan2(velocity_x), Release);
         }
+        self.speed
+            .store(speed.clamp(physics.min_speed, physics.max_speed), Release);
+    }
 
-        None
+    /// Moves the ball out of another ball it overlaps with, keeping it on the screen. The ball is not moved if it would
+    /// end up in a wall of the field hitbox, as it could get stuck in there.
+    fn push_to(&self, center_x: f32, center_y: f32) {
+        let radius = self.config.radius;
+        let (center_x, center_y) = self.clamp_to_screen(center_x, center_y);
+
+        let touches_wall = trap::free_spot_coordinates(
+            center_x as i16,
+            center_y as i16,
+            radius,
+            self.viewport.width,
+            self.viewport.height,
+        )
+        .into_iter()
+        .any(|(x, y)| canvas::is_hitbox_wall(&self.field.hitbox, x, y));
+        if !touches_wall {
+            self.set_center(center_x, center_y);
+        }
+    }
+
+    /// Keeps the whole ball within the viewport
+    fn clamp_to_screen(&self, center_x: f32, center_y: f32) -> (f32, f32) {
+        let radius = self.config.radius;
+        (
+            center_x.clamp(radius, (self.viewport.width as f32 - radius).max(radius)),
+            center_y.clamp(radius, (self.viewport.height as f32 - radius).max(radius)),
+        )
     }
 
     pub fn reset(&self) {
-        self.center_x
-            .store(((self.screen_width - BALL_IMAGE_SIZE) / 2) as f32, Release);
-        self.center_y
-            .store(((self.screen_height - BALL_IMAGE_SIZE) / 2) as f32, Release);
+        self.center_x.store(self.kick_off.0, Release);
+        self.center_y.store(self.kick_off.1, Release);
         self.dir
-            .store(rand::thread_rng().gen_range(-PI..PI), Release);
+            .store(self.rng.lock().unwrap().gen_range(-PI..PI), Release);
+        self.speed.store(self.config.physics.speed, Release);
+        self.trapped_ticks.store(0, Release);
+        self.pass_through_ticks.store(0, Release);
+        *self.last_touch.lock().unwrap() = None;
+        *self.previous_touch.lock().unwrap() = None;
+        *self.previous_contact.lock().unwrap() = None;
+        self.events.publish(GameEvent::Reset { ball: self.id });
     }
 }
 
 #[async_trait]
 impl Draw for Ball {
-    async fn draw(&self, client: &mut Client) -> Result<()> {
-        client
-            .write_bytes(self.draw_command_bytes.read().await.as_ref())
-            .await?;
+    async fn draw<C: Canvas>(&self, canvas: &mut C) -> io::Result<()> {
+        match &self.offset_draw_command_bytes {
+            Some(offset_draw_command_bytes) => {
+                let (x, y) = self.image_position();
+                // Send the offset and the pixels in one write, so they are not split up into separate packets
+                let mut command_bytes = Vec::with_capacity(offset_draw_command_bytes.len() + 32);
+                PixelflutRequest::SetOffset { x, y }.serialize(&mut command_bytes, self.encoding);
+                command_bytes.extend_from_slice(offset_draw_command_bytes);
+
+                canvas.write_bytes(&command_bytes).await?;
+            }
+            None => {
+                canvas
+                    .write_bytes(self.draw_command_bytes.read().await.as_ref())
+                    .await?;
+            }
+        }
         Ok(())
     }
 }
+
+/// Lets all balls that overlap bounce off each other. As all balls have the same mass, an elastic collision simply
+/// exchanges the parts of their velocities along the line connecting their centers. The resulting speeds are kept
+/// within the configured bounds, so that the balls don't speed each other up over time. Afterwards the balls are pushed
+/// apart, so they don't collide again in the next tick.
+/// Pairs are processed in a fixed order, so the outcome only depends on the state of the balls.
+pub fn collide_balls(balls: &[Arc<Ball>]) {
+    for (i, ball) in balls.iter().enumerate() {
+        for other in &balls[i + 1..] {
+            let (x, y) = (ball.center_x.load(Acquire), ball.center_y.load(Acquire));
+            let (other_x, other_y) = (other.center_x.load(Acquire), other.center_y.load(Acquire));
+            let distance = f32::sqrt((other_x - x).powi(2) + (other_y - y).powi(2));
+            let min_distance = ball.config.radius + other.config.radius;
+            // Balls at exactly the same position (e.g. after kick-off) have no direction to bounce off to
+            if distance == 0.0 || distance >= min_distance {
+                continue;
+            }
+
+            // Normal pointing from ball to other
+            let (normal_x, normal_y) = ((other_x - x) / distance, (other_y - y) / distance);
+            let (velocity_x, velocity_y) = ball.velocity();
+            let (other_velocity_x, other_velocity_y) = other.velocity();
+
+            // Only bounce if they move towards each other, otherwise they are already separating
+            let approaching_speed = (velocity_x - other_velocity_x) * normal_x
+                + (velocity_y - other_velocity_y) * normal_y;
+            if approaching_speed > 0.0 {
+                ball.set_velocity(
+                    velocity_x - approaching_speed * normal_x,
+                    velocity_y - approaching_speed * normal_y,
+                );
+                other.set_velocity(
+                    other_velocity_x + approaching_speed * normal_x,
+                    other_velocity_y + approaching_speed * normal_y,
+                );
+            }
+
+            let push = (min_distance - distance) / 2.0;
+            ball.push_to(x - push * normal_x, y - push * normal_y);
+            other.push_to(other_x + push * normal_x, other_y + push * normal_y);
+        }
+    }
+}
@@ -0,0 +1,49 @@
+//! Minimal Pixelflut server, so that pixel-soccer can be developed without a separate server.
+//! See [`Server`] for the supported commands.
+
+use clap::Parser;
+use pixel_soccer::server::Server;
+use std::{path::PathBuf, sync::Arc, time::Duration};
+use tokio::{io::Result, net::TcpListener};
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    /// Address to listen on
+    #[clap(short, long, default_value = "[::]:1234")]
+    listen_address: String,
+
+    #[clap(long, default_value = "1920")]
+    width: u16,
+
+    #[clap(long, default_value = "1080")]
+    height: u16,
+
+    /// Periodically save the canvas as PNG to this file, e.g. to watch a game without a display
+    #[clap(long)]
+    png_dump: Option<PathBuf>,
+
+    /// Seconds between two PNG dumps
+    #[clap(long, default_value = "5")]
+    png_dump_interval: u64,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+
+    let server = Arc::new(Server::new(args.width, args.height));
+
+    if let Some(path) = args.png_dump {
+        let server = Arc::clone(&server);
+        let interval = Duration::from_secs(args.png_dump_interval.max(1));
+        tokio::spawn(async move { server.dump_png(path, interval).await });
+    }
+
+    let listener = TcpListener::bind(&args.listen_address).await?;
+    println!(
+        "Listening on {} with a canvas of {}x{}",
+        args.listen_address, args.width, args.height
+    );
+    server.serve(listener).await
+}
@@ -0,0 +1,319 @@
+use async_trait::async_trait;
+use image::{DynamicImage, GenericImageView};
+use std::io;
+
+use crate::{
+    client,
+    events::{Connection, Events},
+    image_helpers::get_donut_coordinates,
+    protocol::{Encoding, PixelflutRequest, ProtocolError, ServerCapabilities},
+    viewport::Viewport,
+};
+
+/// Put into probes at the pixels where the field hitbox has a wall, e.g. around the goals.
+/// This is not a valid rgb value, so it can't be confused with a pixel read from the server.
+pub const FIELD_HITBOX_COLOR: u32 = 0x0100_0000;
+
+/// Walls are painted in opaque red on the field hitbox
+pub fn is_hitbox_wall(field_hitbox: &DynamicImage, x: u16, y: u16) -> bool {
+    let value = field_hitbox.get_pixel(x as u32, y as u32).0;
+    value[0] == 255 && value[1] == 0 && value[2] == 0 && value[3] != 0
+}
+
+/// Something the game can be played on: Either a Pixelflut server ([`Client`][crate::client::Client]) or an image in
+/// memory ([`MemoryCanvas`][crate::memory_canvas::MemoryCanvas]), e.g. to run the game logic without a server.
+///
+/// Pixel reads are split into sending the requests and reading the responses, so that the latency of the network can
+/// be hidden. Responses arrive in the order the reads were requested.
+#[async_trait]
+pub trait Canvas: Send + Sync + Sized {
+    /// Opens another independent connection to the same canvas, e.g. for a separate drawing task
+    async fn new_connection(&self) -> io::Result<Self>;
+
+    /// Replaces a broken connection with a new one, retrying until it succeeds.
+    /// All pixel reads that were not received yet are lost. Every attempt is published on `events`, naming the
+    /// `connection` the canvas is used for.
+    async fn reconnect(&mut self, connection: Connection, events: &Events);
+
+    /// Size of the [viewport][Self::viewport], if there is one
+    async fn get_screen_size(&mut self) -> Result<(u16, u16), ProtocolError>;
+
+    async fn probe_capabilities(&mut self) -> Result<ServerCapabilities, ProtocolError>;
+
+    async fn write_bytes(&mut self, bytes: &[u8]) -> io::Result<()>;
+
+    /// Sends a read for every of the given pixels, without waiting for the responses
+    async fn request_pixels(&mut self, coordinates: &[(u16, u16)]) -> io::Result<()>;
+
+    /// Reads the response to the oldest pixel read, returning the coordinates and rgb value of the pixel
+    async fn read_pixel(&mut self) -> Result<(u16, u16, u32), ProtocolError>;
+
+    /// Changes every time the connection is replaced, so that we know if responses of reads requested earlier can still
+    /// arrive
+    fn connection_id(&self) -> u64;
+
+    /// Region of the screen the canvas is confined to. Pixel reads use coordinates relative to it, but draw commands
+    /// are prepared in advance and need to be translated with [`Viewport::to_screen`] by whoever creates them.
+    fn viewport(&self) -> Option<Viewport> {
+        None
+    }
+
+    /// Slow. For best performance use [write_bytes][Self::write_bytes]
+    async fn write_commands(
+        &mut self,
+        commands: &[PixelflutRequest],
+        encoding: Encoding,
+    ) -> io::Result<()> {
+        let bytes = client::commands_to_bytes(commands, encoding);
+        self.write_bytes(&bytes).await
+    }
+
+    /// `x_offset` and `y_offset` are allowed to be negative or too high, so that the screen bounds are exceeded.
+    /// This function will handle that cases and fill the returned rectangle with 0s if they are out of bounds.
+    async fn get_screen_rect(
+        &mut self,
+        x_offset: i16,
+        y_offset: i16,
+        width: u16,
+        height: u16,
+        screen_width: u16,
+        screen_height: u16,
+    ) -> Result<Vec<Vec<u32>>, ProtocolError> {
+        let mut coordinates = Vec::with_capacity(width as usize * height as usize);
+        for x in x_offset..x_offset + width as i16 {
+            for y in y_offset..y_offset + height as i16 {
+                if x >= 0 && x < screen_width as i16 && y >= 0 && y < screen_height as i16 {
+                    coordinates.push((x as u16, y as u16));
+                }
+            }
+        }
+        self.request_pixels(&coordinates).await?;
+
+        let mut result = vec![vec![0_u32; height as usize]; width as usize];
+        let mut first_error = None;
+        for _ in 0..coordinates.len() {
+            let (x, y, rgb) = match self.read_pixel().await {
+                Ok(pixel) => pixel,
+                Err(err) if err.is_connection_error() => return Err(err),
+                Err(err) => {
+                    first_error.get_or_insert(err);
+                    continue;
+                }
+            };
+            match result
+                .get_mut((x as i16 - x_offset) as usize)
+                .and_then(|column| column.get_mut((y as i16 - y_offset) as usize))
+            {
+                Some(cell) => *cell = rgb,
+                None => {
+                    first_error.get_or_insert(ProtocolError::UnexpectedResponse(format!(
+                        "PX {x} {y} {rgb:06x}"
+                    )));
+                }
+            }
+        }
+
+        match first_error {
+            Some(err) => Err(err),
+            None => Ok(result),
+        }
+    }
+
+    /// `x_center` and `y_center` are allowed to be negative or too high, so that the screen bounds are exceeded.
+    /// This function will handle that cases and fill the returned rectangle with 0s if they are out of bounds.
+    /// Also all parts of the returned rect that are not part of the requested donut will be 0s.
+    #[allow(clippy::too_many_arguments)]
+    async fn get_screen_donut(
+        &mut self,
+        x_center: i16,
+        y_center: i16,
+        inner_circle_radius: f32,
+        outer_circle_radius: f32,
+        screen_width: u16,
+        screen_height: u16,
+        field_hitbox: Option<&DynamicImage>,
+    ) -> Result<Vec<Vec<u32>>, ProtocolError> {
+        let donut_coordinates = get_donut_coordinates(
+            x_center,
+            y_center,
+            inner_circle_radius,
+            outer_circle_radius,
+            screen_width,
+            screen_height,
+        );
+        let pending = self
+            .request_screen_pixels(
+                x_center,
+                y_center,
+                outer_circle_radius,
+                donut_coordinates,
+                field_hitbox,
+            )
+            .await?;
+        self.receive_screen_donut(pending).await
+    }
+
+    /// First half of [`get_screen_donut`][Self::get_screen_donut]: Only requests the pixel reads for the given
+    /// `coordinates` (e.g. a donut or an arc), without waiting for the responses. This way the network latency can be
+    /// hidden by doing other work (or simply waiting for the next tick) before calling
+    /// [`receive_screen_donut`][Self::receive_screen_donut]. All coordinates must be within `outer_circle_radius`
+    /// around the center.
+    ///
+    /// Responses are read in the order the requests were sent, so every [`PendingDonut`] must be either received or
+    /// [discarded][Self::discard_screen_donut] before a later one can be received.
+    async fn request_screen_pixels(
+        &mut self,
+        x_center: i16,
+        y_center: i16,
+        outer_circle_radius: f32,
+        coordinates: Vec<(u16, u16)>,
+        field_hitbox: Option<&DynamicImage>,
+    ) -> io::Result<PendingDonut> {
+        let mut result =
+            vec![vec![0_u32; 2 * outer_circle_radius as usize]; 2 * outer_circle_radius as usize];
+        let mut reads = Vec::with_capacity(coordinates.len());
+
+        for &(x, y) in &coordinates {
+            if let Some(field_hitbox) = field_hitbox {
+                // When the hitbox says red their will be a collision with e.g. a goal, so we must merge that on top of the regular reading process
+                if is_hitbox_wall(field_hitbox, x, y) {
+                    result[(x as i16 - x_center + outer_circle_radius as i16) as usize]
+                        [(y as i16 - y_center + outer_circle_radius as i16) as usize] =
+                        FIELD_HITBOX_COLOR;
+                    // We already set the need value, we need to skip the regular reading of the color
+                    continue;
+                }
+            }
+
+            reads.push((x, y));
+        }
+
+        self.request_pixels(&reads).await?;
+
+        Ok(PendingDonut {
+            connection_id: self.connection_id(),
+            x_center,
+            y_center,
+            outer_circle_radius,
+            coordinates,
+            result,
+            pending_reads: reads.len(),
+        })
+    }
+
+    /// Second half of [`get_screen_donut`][Self::get_screen_donut], reads the responses of the pixel reads.
+    async fn receive_screen_donut(
+        &mut self,
+        pending: PendingDonut,
+    ) -> Result<Vec<Vec<u32>>, ProtocolError> {
+        if !self.is_current(&pending) {
+            return Err(ProtocolError::UnexpectedResponse(
+                "the pixel reads were sent on a previous connection".to_string(),
+            ));
+        }
+
+        let PendingDonut {
+            x_center,
+            y_center,
+            outer_circle_radius,
+            mut result,
+            pending_reads,
+            ..
+        } = pending;
+
+        let mut first_error = None;
+        for _ in 0..pending_reads {
+            let (x, y, rgb) = match self.read_pixel().await {
+                Ok(pixel) => pixel,
+                Err(err) if err.is_connection_error() => return Err(err),
+                Err(err) => {
+                    first_error.get_or_insert(err);
+                    continue;
+                }
+            };
+
+            // A pixel we did not ask for must not crash us by indexing out of bounds
+            match result
+                .get_mut((x as i16 - x_center + outer_circle_radius as i16) as usize)
+                .and_then(|column| {
+                    column.get_mut((y as i16 - y_center + outer_circle_radius as i16) as usize)
+                }) {
+                Some(cell) => *cell = rgb,
+                None => {
+                    first_error.get_or_insert(ProtocolError::UnexpectedResponse(format!(
+                        "PX {x} {y} {rgb:06x}"
+                    )));
+                }
+            }
+        }
+
+        match first_error {
+            Some(err) => Err(err),
+            None => Ok(result),
+        }
+    }
+
+    /// Reads and throws away the responses of a [`PendingDonut`] that is not needed any more, e.g. because the ball
+    /// was reset in the meantime.
+    async fn discard_screen_donut(&mut self, pending: PendingDonut) -> Result<(), ProtocolError> {
+        // Responses to reads on a previous connection are gone anyway
+        if !self.is_current(&pending) {
+            return Ok(());
+        }
+
+        for _ in 0..pending.pending_reads {
+            match self.read_pixel().await {
+                Ok(_) => (),
+                Err(err) if err.is_connection_error() => return Err(err),
+                Err(_) => (),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks if the responses of the given [`PendingDonut`] can still be read from the current connection
+    fn is_current(&self, pending: &PendingDonut) -> bool {
+        pending.connection_id == self.connection_id()
+    }
+}
+
+/// Pixel reads of a donut (or a part of it) that were requested, but whose responses were not read yet.
+/// See [`Canvas::request_screen_pixels`].
+pub struct PendingDonut {
+    /// The connection the reads were sent on, as the responses are lost when reconnecting
+    connection_id: u64,
+    x_center: i16,
+    y_center: i16,
+    outer_circle_radius: f32,
+    /// All pixels that are part of the probe, including the ones taken from the hitbox
+    coordinates: Vec<(u16, u16)>,
+    /// Already contains the pixels from the hitbox
+    result: Vec<Vec<u32>>,
+    pending_reads: usize,
+}
+
+impl PendingDonut {
+    /// Number of pixels that are part of the probe, including the ones taken from the hitbox
+    pub fn len(&self) -> usize {
+        self.coordinates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.coordinates.is_empty()
+    }
+
+    /// Checks if these are the reads that would be requested with the given parameters
+    pub fn matches(
+        &self,
+        x_center: i16,
+        y_center: i16,
+        outer_circle_radius: f32,
+        coordinates: &[(u16, u16)],
+    ) -> bool {
+        self.x_center == x_center
+            && self.y_center == y_center
+            && self.outer_circle_radius == outer_circle_radius
+            && self.coordinates == coordinates
+    }
+}
@@ -1,220 +1,338 @@
-use core::panic;
-use image::{DynamicImage, GenericImageView};
+use async_trait::async_trait;
 use lazy_static::lazy_static;
+use rand::{thread_rng, Rng};
 use regex::Regex;
-use std::{io::Result, vec};
+use std::{io, time::Duration};
 use tokio::{
-    io::{AsyncBufReadExt, AsyncWriteExt, BufStream},
-    net::TcpStream,
+    io::{AsyncReadExt, AsyncWriteExt, BufWriter},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+    sync::mpsc,
+    task::JoinHandle,
+    time,
 };
 
 use crate::{
-    ball::TARGET_COLOR,
-    image_helpers::get_donut_coordinates,
-    protocol::{PixelflutRequest, PixelflutResponse, Serialize},
+    canvas::Canvas,
+    events::{Connection, Events, GameEvent},
+    protocol::{
+        Encoding, PixelflutRequest, PixelflutResponse, ProtocolError, Serialize, ServerCapabilities,
+    },
+    viewport::Viewport,
 };
 
-pub const AVG_BYES_PER_PIXEL_SET_COMMAND: usize = "PX 123 123 ffffff\n".len();
-
 lazy_static! {
     // Thanks to https://github.com/timvisee/pixelpwnr/blob/0d83b3e0b54448a59844e330a36f2e4b0e19e611/src/pix/client.rs#L19
     pub static ref SIZE_COMMAND_REGEX: Regex = Regex::new(r"^(?i)\s*SIZE\s+([[:digit:]]+)\s+([[:digit:]]+)\s*$").unwrap();
     pub static ref READ_PIXEL_COMMAND_REGEX: Regex = Regex::new(r"PX ([0-9]+) ([0-9]+) ([0-9a-fA-F]+)\s").unwrap();
 }
 
+const RECONNECT_INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+const RECONNECT_MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+/// Maximum number of bytes the reader task reads from the socket at once
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+/// Number of chunks the reader task can be ahead of the parsing, before it stops reading from the socket
+const READ_CHUNK_QUEUE: usize = 64;
+
+/// The reading and writing half of the connection are independent, so that e.g. pixel reads for the next tick can be
+/// sent before the responses of the current tick are parsed.
+/// The reading half is owned by a separate task, which keeps draining the socket while we are busy writing, so that the
+/// server never blocks on sending us responses.
 pub struct Client {
-    stream: BufStream<TcpStream>,
+    /// Chunks read from the socket by the reader task. Closed once the server closed the connection.
+    responses: mpsc::Receiver<io::Result<Vec<u8>>>,
+    reader_task: JoinHandle<()>,
+    /// Last chunk received from the reader task and the position up to which it was parsed
+    read_buffer: Vec<u8>,
+    read_position: usize,
+    writer: BufWriter<OwnedWriteHalf>,
+    /// Reused for every line read, so that parsing responses does not allocate
+    line_buffer: Vec<u8>,
+    /// Reused for serializing read requests
+    request_buffer: Vec<u8>,
+    /// Incremented on every reconnect, so that we know if responses of requests sent earlier can still arrive
+    connection_id: u64,
+
+    server_address: String,
+    /// When set, the game only sees this part of the screen
+    viewport: Option<Viewport>,
+    /// Size of the screen the first time it was asked for.
+    /// After reconnecting the server must report the same size, as e.g. the ball positions depend on it.
+    screen_size: Option<(u16, u16)>,
 }
 
 impl Client {
-    pub async fn new(server_address: &str) -> Result<Self> {
-        let stream = TcpStream::connect(server_address).await?;
+    /// All pixel reads and the screen size are confined to the given `viewport`, see [`Canvas::viewport`]
+    pub async fn new(server_address: &str, viewport: Option<Viewport>) -> io::Result<Self> {
+        let (reader, writer) = TcpStream::connect(server_address).await?.into_split();
+        let (responses, reader_task) = spawn_reader(reader);
         Ok(Client {
-            stream: BufStream::new(stream),
+            responses,
+            reader_task,
+            read_buffer: Vec::new(),
+            read_position: 0,
+            writer: BufWriter::new(writer),
+            line_buffer: Vec::new(),
+            request_buffer: Vec::new(),
+            connection_id: 0,
+            server_address: server_address.to_owned(),
+            viewport,
+            screen_size: None,
         })
     }
 
-    pub async fn write_bytes(&mut self, bytes: &[u8]) -> Result<()> {
-        self.stream.write_all(bytes).await?;
-        self.stream.flush().await?;
-        Ok(())
-    }
+    async fn try_reconnect(&mut self) -> Result<(), ProtocolError> {
+        let (reader, writer) = TcpStream::connect(&self.server_address).await?.into_split();
+        // Responses still in flight on the old connection will never be read
+        self.reader_task.abort();
+        (self.responses, self.reader_task) = spawn_reader(reader);
+        self.read_buffer.clear();
+        self.read_position = 0;
+        self.writer = BufWriter::new(writer);
+        self.connection_id += 1;
 
-    /// Slow. For best performance use [write_bytes][Self::write_bytes]
-    pub async fn write_commands(&mut self, commands: &[PixelflutRequest]) -> Result<()> {
-        let bytes = commands_to_bytes(commands);
-        self.write_bytes(&bytes).await?;
+        if let Some(expected_size) = self.screen_size {
+            // get_screen_size only remembers the first size, so we can compare against it
+            let size = self.get_screen_size().await?;
+            if size != expected_size {
+                return Err(ProtocolError::UnexpectedResponse(format!(
+                    "screen size changed from {expected_size:?} to {size:?}"
+                )));
+            }
+        }
 
         Ok(())
     }
 
+    /// Reads `number_of_commands` responses. In case some of them can not be parsed, all remaining responses are
+    /// still read, so that the connection stays usable, and the first error is returned afterwards.
     pub async fn read_commands(
         &mut self,
         number_of_commands: usize,
-    ) -> Result<Vec<PixelflutResponse>> {
-        let mut result = Vec::new();
+    ) -> Result<Vec<PixelflutResponse>, ProtocolError> {
+        let mut result = Vec::with_capacity(number_of_commands);
+        let mut first_error = None;
         for _ in 0..number_of_commands {
-            let mut buffer = String::new();
-            self.stream.read_line(&mut buffer).await?;
-
-            let mut parts = buffer.split(' ');
-            match parts.next() {
-                Some("PX") => {
-                    let x = parts
-                        .next()
-                        .expect("invalid PX response - missing x coordinate")
-                        .parse::<u16>()
-                        .unwrap();
-                    let y = parts
-                        .next()
-                        .expect("invalid PX response - missing y coordinate")
-                        .parse::<u16>()
-                        .unwrap();
-                    let rgb = u32::from_str_radix(
-                        parts
-                            .next()
-                            .expect("invalid PX response - missing rgb color")
-                            .trim_end_matches('\n'),
-                        16,
-                    )
-                    .unwrap();
-                    result.push(PixelflutResponse::Pixel { x, y, rgb });
-                }
-                Some("SIZE") => {
-                    let width = parts
-                        .next()
-                        .expect("invalid SIZE response - missing width")
-                        .parse::<u16>()
-                        .unwrap();
-                    let height = parts
-                        .next()
-                        .expect("invalid SIZE response - missing height")
-                        .trim_end_matches('\n')
-                        .parse::<u16>()
-                        .unwrap();
-                    result.push(PixelflutResponse::Size { width, height });
+            match self.read_response().await {
+                Ok(response) => result.push(response),
+                Err(err) if err.is_connection_error() => return Err(err),
+                Err(err) => {
+                    first_error.get_or_insert(err);
                 }
-                None | Some(_) => panic!("Could not read response {buffer:?}"),
             }
         }
 
-        Ok(result)
+        match first_error {
+            Some(err) => Err(err),
+            None => Ok(result),
+        }
     }
 
-    pub async fn get_screen_size(&mut self) -> Result<(u16, u16)> {
-        self.write_commands(&[PixelflutRequest::GetSize]).await?;
-        let response = self.read_commands(1).await?;
+    /// Reads a single line into the reused line buffer, so no allocations are needed per response.
+    /// Returns the line including the trailing newline.
+    async fn read_line(&mut self) -> Result<&str, ProtocolError> {
+        self.line_buffer.clear();
+        loop {
+            let unread = &self.read_buffer[self.read_position..];
+            if let Some(end) = unread.iter().position(|byte| *byte == b'\n') {
+                self.line_buffer.extend_from_slice(&unread[..=end]);
+                self.read_position += end + 1;
+                break;
+            }
+            self.line_buffer.extend_from_slice(unread);
 
-        if let Some(PixelflutResponse::Size { width, height }) = response.get(0) {
-            Ok((*width, *height))
-        } else {
-            panic!("Expected to get the size of the screen, but got {response:?}")
+            self.read_buffer = match self.responses.recv().await {
+                Some(chunk) => chunk?,
+                // Like a buffered reader, return the last line even if it is not terminated
+                None if !self.line_buffer.is_empty() => break,
+                None => return Err(ProtocolError::Eof),
+            };
+            self.read_position = 0;
         }
+
+        std::str::from_utf8(&self.line_buffer).map_err(|_| {
+            ProtocolError::UnexpectedResponse(String::from_utf8_lossy(&self.line_buffer).into())
+        })
     }
 
-    /// `x_offset` and `y_offset` are allowed to be negative or too high, so that the screen bounds are exceeded.
-    /// This function will handle that cases and fill the returned rectangle with 0s if they are out of bounds.
-    #[allow(dead_code)]
-    pub async fn get_screen_rect(
-        &mut self,
-        x_offset: i16,
-        y_offset: i16,
-        width: u16,
-        height: u16,
-        screen_width: u16,
-        screen_height: u16,
-    ) -> Result<Vec<Vec<u32>>> {
-        let mut read_commands = Vec::with_capacity(width as usize * height as usize);
-        for x in x_offset..x_offset + width as i16 {
-            for y in y_offset..y_offset + height as i16 {
-                if x >= 0 && x < screen_width as i16 && y >= 0 && y < screen_height as i16 {
-                    read_commands.push(PixelflutRequest::GetPixel {
-                        x: x as u16,
-                        y: y as u16,
-                    });
-                }
+    async fn read_response(&mut self) -> Result<PixelflutResponse, ProtocolError> {
+        PixelflutResponse::parse(self.read_line().await?)
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        self.reader_task.abort();
+    }
+}
+
+/// Spawns a task reading everything the server sends on the given connection. The chunks are passed on through the
+/// returned channel, which is closed when the server closes the connection or reading fails.
+fn spawn_reader(
+    mut reader: OwnedReadHalf,
+) -> (mpsc::Receiver<io::Result<Vec<u8>>>, JoinHandle<()>) {
+    let (sender, receiver) = mpsc::channel(READ_CHUNK_QUEUE);
+    let task = tokio::spawn(async move {
+        let mut buffer = vec![0; READ_CHUNK_SIZE];
+        loop {
+            let result = match reader.read(&mut buffer).await {
+                Ok(0) => return,
+                Ok(read) => Ok(buffer[..read].to_vec()),
+                Err(err) => Err(err),
+            };
+            let failed = result.is_err();
+            if sender.send(result).await.is_err() || failed {
+                return;
             }
         }
-        self.write_commands(&read_commands).await?;
-
-        let mut result = vec![vec![0_u32; height as usize]; width as usize];
-        let responses = self.read_commands(read_commands.len()).await?;
-        for response in responses {
-            match response {
-                PixelflutResponse::Pixel { x, y, rgb } => {
-                    result[(x as i16 - x_offset) as usize][(y as i16 - y_offset) as usize] = rgb;
+    });
+    (receiver, task)
+}
+
+#[async_trait]
+impl Canvas for Client {
+    async fn new_connection(&self) -> io::Result<Self> {
+        let mut client = Client::new(&self.server_address, self.viewport).await?;
+        // Remember the screen size, so that it can be validated when reconnecting
+        if self.screen_size.is_some() {
+            client.get_screen_size().await?;
+        }
+        Ok(client)
+    }
+
+    /// Replaces the connection with a new one, retrying with exponential backoff and jitter until it succeeds.
+    /// If the screen size was requested before, the new connection is only accepted if the server still reports the
+    /// same size.
+    async fn reconnect(&mut self, connection: Connection, events: &Events) {
+        let mut backoff = RECONNECT_INITIAL_BACKOFF;
+        loop {
+            // Add up to 50% jitter, so that all our connections don't hammer the server at the same time
+            let jitter = backoff.mul_f32(thread_rng().gen_range(0.0..0.5));
+            time::sleep(backoff + jitter).await;
+            backoff = (backoff * 2).min(RECONNECT_MAX_BACKOFF);
+
+            match self.try_reconnect().await {
+                Ok(()) => {
+                    events.publish(GameEvent::Reconnected { connection });
+                    return;
                 }
-                _ => panic!("Expected to get the color of a pixel, but got {response:?}"),
+                Err(err) => events.publish(GameEvent::ReconnectFailed {
+                    connection,
+                    error: err.to_string(),
+                    retry_in: backoff,
+                }),
             }
         }
+    }
+
+    async fn get_screen_size(&mut self) -> Result<(u16, u16), ProtocolError> {
+        self.write_commands(&[PixelflutRequest::GetSize], Encoding::Ascii)
+            .await?;
+        let response = self.read_commands(1).await?;
 
-        Ok(result)
+        let Some(&PixelflutResponse::Size { width, height }) = response.first() else {
+            return Err(ProtocolError::UnexpectedResponse(format!("{response:?}")));
+        };
+        let size = match self.viewport {
+            Some(viewport) if !viewport.fits_into(width, height) => {
+                return Err(ProtocolError::Io(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "the viewport {viewport} does not fit into the screen of {width}x{height}"
+                    ),
+                )))
+            }
+            Some(viewport) => (viewport.width, viewport.height),
+            None => (width, height),
+        };
+        self.screen_size.get_or_insert(size);
+        Ok(size)
     }
 
-    /// `x_center` and `y_center` are allowed to be negative or too high, so that the screen bounds are exceeded.
-    /// This function will handle that cases and fill the returned rectangle with 0s if they are out of bounds.
-    /// Also all parts of the returned rect that are not part of the requested donut will be 0s.
-    #[allow(clippy::too_many_arguments)]
-    pub async fn get_screen_donut(
-        &mut self,
-        x_center: i16,
-        y_center: i16,
-        inner_circle_radius: f32,
-        outer_circle_radius: f32,
-        screen_width: u16,
-        screen_height: u16,
-        field_hitbox: Option<&DynamicImage>,
-    ) -> Result<Vec<Vec<u32>>> {
-        let mut result =
-            vec![vec![0_u32; 2 * outer_circle_radius as usize]; 2 * outer_circle_radius as usize];
-        let mut read_commands = Vec::new();
-
-        let donut_coordinates = get_donut_coordinates(
-            x_center,
-            y_center,
-            inner_circle_radius,
-            outer_circle_radius,
-            screen_width,
-            screen_height,
-        );
-        for (x, y) in donut_coordinates {
-            if let Some(field_hitbox) = field_hitbox {
-                let value = field_hitbox.get_pixel(x as u32, y as u32).0;
-                // When the hitbox says red their will be a collision with e.g. a goal, so we must merge that on top of the regular reading process
-                if value[0] == 255 && value[1] == 0 && value[2] == 0 && value[3] != 0 {
-                    result[(x as i16 - x_center + outer_circle_radius as i16) as usize]
-                        [(y as i16 - y_center + outer_circle_radius as i16) as usize] =
-                        TARGET_COLOR;
-                    // We already set the need value, we need to skip the regular reading of the color
-                    continue;
-                }
+    /// Asks the server for `HELP` to find out which features it supports.
+    /// As the length of the help text is unknown, a `SIZE` command is sent afterwards and everything until the size
+    /// response is considered to be the help text. A single pixel read is issued as well, as the help text is not a
+    /// reliable source to determine if the server supports reading pixels.
+    async fn probe_capabilities(&mut self) -> Result<ServerCapabilities, ProtocolError> {
+        self.write_commands(
+            &[
+                PixelflutRequest::Help,
+                PixelflutRequest::GetPixel { x: 0, y: 0 },
+                PixelflutRequest::GetSize,
+            ],
+            Encoding::Ascii,
+        )
+        .await?;
+
+        let mut help_text = String::new();
+        let mut pixel_reads = false;
+        loop {
+            let line = self.read_line().await?;
+            if SIZE_COMMAND_REGEX.is_match(line) {
+                break;
+            } else if READ_PIXEL_COMMAND_REGEX.is_match(line) {
+                pixel_reads = true;
+            } else {
+                help_text.push_str(line);
             }
+        }
 
-            read_commands.push(PixelflutRequest::GetPixel {
-                x: x as u16,
-                y: y as u16,
-            });
+        Ok(ServerCapabilities::from_help_text(&help_text, pixel_reads))
+    }
+
+    async fn write_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.writer.write_all(bytes).await?;
+        self.writer.flush().await?;
+        Ok(())
+    }
+
+    async fn request_pixels(&mut self, coordinates: &[(u16, u16)]) -> io::Result<()> {
+        self.request_buffer.clear();
+        for &(x, y) in coordinates {
+            let (x, y) = match self.viewport {
+                Some(viewport) => viewport.to_screen(x, y),
+                None => (x, y),
+            };
+            PixelflutRequest::GetPixel { x, y }
+                .serialize(&mut self.request_buffer, Encoding::Ascii);
         }
 
-        self.write_commands(&read_commands).await?;
+        self.writer.write_all(&self.request_buffer).await?;
+        self.writer.flush().await?;
+        Ok(())
+    }
 
-        let responses = self.read_commands(read_commands.len()).await?;
-        for response in responses {
-            match response {
-                PixelflutResponse::Pixel { x, y, rgb } => {
-                    result[(x as i16 - x_center + outer_circle_radius as i16) as usize]
-                        [(y as i16 - y_center + outer_circle_radius as i16) as usize] = rgb;
-                }
-                _ => panic!("Expected to get the color of a pixel, but got {response:?}"),
-            }
+    async fn read_pixel(&mut self) -> Result<(u16, u16, u32), ProtocolError> {
+        match self.read_response().await? {
+            PixelflutResponse::Pixel { x, y, rgb } => match self.viewport {
+                Some(viewport) => viewport
+                    .to_viewport(x, y)
+                    .map(|(x, y)| (x, y, rgb))
+                    .ok_or_else(|| {
+                        ProtocolError::UnexpectedResponse(format!("PX {x} {y} {rgb:06x}"))
+                    }),
+                None => Ok((x, y, rgb)),
+            },
+            response => Err(ProtocolError::UnexpectedResponse(format!("{response:?}"))),
         }
+    }
+
+    fn connection_id(&self) -> u64 {
+        self.connection_id
+    }
 
-        Ok(result)
+    fn viewport(&self) -> Option<Viewport> {
+        self.viewport
     }
 }
 
-pub fn commands_to_bytes(commands: &[PixelflutRequest]) -> Vec<u8> {
-    let mut bytes = Vec::new();
-    commands.iter().for_each(|cmd| cmd.serialize(&mut bytes));
+pub fn commands_to_bytes(commands: &[PixelflutRequest], encoding: Encoding) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(commands.len() * encoding.avg_bytes_per_pixel_set_command());
+    commands
+        .iter()
+        .for_each(|cmd| cmd.serialize(&mut bytes, encoding));
     bytes
 }
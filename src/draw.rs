@@ -3,31 +3,43 @@ use std::{io::Result, sync::Arc};
 use async_trait::async_trait;
 use tokio::task::JoinHandle;
 
-use crate::client::Client;
+use crate::{
+    canvas::Canvas,
+    events::{Connection, Events, GameEvent},
+};
 
 #[async_trait]
 pub trait Draw {
-    async fn draw(&self, client: &mut Client) -> Result<()>;
+    async fn draw<C: Canvas>(&self, canvas: &mut C) -> Result<()>;
 }
 
-pub async fn start_drawing(
+pub async fn start_drawing<C: Canvas + 'static>(
     object: Arc<impl Draw + std::marker::Send + std::marker::Sync + 'static>,
-    server_address: &str,
+    canvas: &C,
     num_threads: u16,
-) -> Vec<JoinHandle<()>> {
+    events: &Events,
+) -> Result<Vec<JoinHandle<()>>> {
     let mut threads = vec![];
 
     for _ in 0..num_threads {
-        let mut client = Client::new(server_address).await.unwrap();
+        let mut canvas = canvas.new_connection().await?;
         let object_clone = object.clone();
+        let events = events.clone();
 
         let thread = tokio::spawn(async move {
             loop {
-                object_clone.draw(&mut client).await.unwrap();
+                if let Err(err) = object_clone.draw(&mut canvas).await {
+                    events.publish(GameEvent::ConnectionError {
+                        connection: Connection::Draw,
+                        error: err.to_string(),
+                        reconnecting: true,
+                    });
+                    canvas.reconnect(Connection::Draw, &events).await;
+                }
             }
         });
         threads.push(thread);
     }
 
-    threads
+    Ok(threads)
 }
@@ -0,0 +1,152 @@
+use std::{fmt::Display, time::Duration};
+
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::{ball::Touch, game::GoalScored, trap::TrapPolicy};
+
+/// Number of events a subscriber can fall behind before it starts missing some
+const CAPACITY: usize = 4096;
+
+/// Something that happened in the game. Balls are identified by their index in [`Game::balls`][crate::game::Game::balls].
+#[derive(Clone, Debug, PartialEq)]
+pub enum GameEvent {
+    /// A ball bounced off a player, a wall of the field hitbox or the edge of the screen
+    Bounce {
+        ball: usize,
+        /// Only set when bouncing off a player
+        touch: Option<Touch>,
+    },
+    /// A ball was surrounded by players and escaped using the given policy
+    Trapped {
+        ball: usize,
+        policy: TrapPolicy,
+    },
+    Goal {
+        ball: usize,
+        goal: GoalScored,
+    },
+    /// The points of the teams changed
+    Score {
+        points_left: u32,
+        points_right: u32,
+    },
+    /// A ball was put back to the kick-off point
+    Reset {
+        ball: usize,
+    },
+    /// Reading or writing pixels failed. For connection errors the connection is replaced afterwards.
+    ConnectionError {
+        connection: Connection,
+        error: String,
+        reconnecting: bool,
+    },
+    /// A broken connection was replaced by a new one
+    Reconnected {
+        connection: Connection,
+    },
+    /// Opening a new connection failed, it is tried again after `retry_in`
+    ReconnectFailed {
+        connection: Connection,
+        error: String,
+        retry_in: Duration,
+    },
+    /// Number of ticks in the last second
+    Fps(u32),
+}
+
+/// What a connection to the canvas is used for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Connection {
+    /// Reading the pixels around the given ball
+    Ball(usize),
+    /// Drawing the field, the score or a ball
+    Draw,
+}
+
+impl Display for Connection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Connection::Ball(ball) => write!(f, "ball {ball}"),
+            Connection::Draw => write!(f, "drawing"),
+        }
+    }
+}
+
+/// Broadcasts [`GameEvent`]s to everybody interested in them, e.g. for logging, statistics or overlays.
+/// Cloning gives another handle to the same channel.
+#[derive(Clone)]
+pub struct Events {
+    sender: broadcast::Sender<GameEvent>,
+}
+
+impl Default for Events {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Events {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(CAPACITY);
+        Events { sender }
+    }
+
+    /// Never blocks. Events nobody subscribed to are simply dropped.
+    pub fn publish(&self, event: GameEvent) {
+        let _ = self.sender.send(event);
+    }
+
+    /// Receives all events published from now on. A subscriber falling behind by more than [`CAPACITY`] events
+    /// misses the oldest ones.
+    pub fn subscribe(&self) -> broadcast::Receiver<GameEvent> {
+        self.sender.subscribe()
+    }
+}
+
+/// Prints the events that are interesting for whoever runs the game, until the channel is closed
+pub async fn log_events(mut receiver: broadcast::Receiver<GameEvent>) {
+    loop {
+        let event = match receiver.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(missed)) => {
+                println!("WARNING: The log can not keep up, missed {missed} events");
+                continue;
+            }
+            Err(RecvError::Closed) => return,
+        };
+
+        match event {
+            GameEvent::Trapped { ball, policy } => {
+                println!("Ball {ball} is trapped, escaping using {policy}")
+            }
+            GameEvent::Goal { ball, goal } => println!(
+                "Ball {ball} went into the {:?} goal, point for the {:?} team{}",
+                goal.goal,
+                goal.scoring_team,
+                if goal.own_goal { " (own goal)" } else { "" }
+            ),
+            GameEvent::ConnectionError {
+                connection,
+                error,
+                reconnecting: true,
+            } => eprintln!("Connection for {connection} failed, reconnecting: {error}"),
+            GameEvent::ConnectionError {
+                connection,
+                error,
+                reconnecting: false,
+            } => eprintln!("Failed to use the connection for {connection}, skipping: {error}"),
+            GameEvent::Reconnected { connection } => {
+                println!("Reconnected the connection for {connection}")
+            }
+            GameEvent::ReconnectFailed {
+                connection,
+                error,
+                retry_in,
+            } => eprintln!(
+                "Failed to reconnect the connection for {connection}, retrying in {retry_in:?}: {error}"
+            ),
+            GameEvent::Fps(fps) => println!("{fps} fps"),
+            GameEvent::Bounce { .. } | GameEvent::Score { .. } | GameEvent::Reset { .. } => (),
+        }
+    }
+}
@@ -1,11 +1,9 @@
 use async_trait::async_trait;
-use image::io::Reader as ImageReader;
-use rand::{prelude::SliceRandom, thread_rng};
+use image::DynamicImage;
+use rand::{prelude::SliceRandom, Rng};
 
 use crate::{
-    client::{self, Client},
-    draw::Draw,
-    image_helpers,
+    canvas::Canvas, client, draw::Draw, image_helpers, protocol::Encoding, viewport::Viewport,
 };
 use std::io::Result;
 
@@ -14,27 +12,28 @@ pub struct Field {
 }
 
 impl Field {
-    pub fn new() -> Self {
-        let image = ImageReader::open("images/field_v3.png")
-            .unwrap()
-            .decode()
-            .unwrap();
-
-        let mut draw_commands = image_helpers::draw_image(&image, 0, 0);
+    /// The image is drawn into the top left corner of the viewport
+    pub fn new(
+        image: &DynamicImage,
+        viewport: &Viewport,
+        encoding: Encoding,
+        rng: &mut impl Rng,
+    ) -> Self {
+        let mut draw_commands = image_helpers::draw_image(image, viewport.x, viewport.y);
 
         // Shuffle commands to prevent drawing artefacts
-        draw_commands.shuffle(&mut thread_rng());
+        draw_commands.shuffle(rng);
 
         Self {
-            draw_command_bytes: client::commands_to_bytes(&draw_commands),
+            draw_command_bytes: client::commands_to_bytes(&draw_commands, encoding),
         }
     }
 }
 
 #[async_trait]
 impl Draw for Field {
-    async fn draw(&self, client: &mut Client) -> Result<()> {
-        client.write_bytes(&self.draw_command_bytes).await?;
+    async fn draw<C: Canvas>(&self, canvas: &mut C) -> Result<()> {
+        canvas.write_bytes(&self.draw_command_bytes).await?;
         Ok(())
     }
 }
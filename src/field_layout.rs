@@ -0,0 +1,292 @@
+use image::{
+    imageops::{self, FilterType},
+    io::Reader as ImageReader,
+    DynamicImage, GenericImageView, Rgba, RgbaImage,
+};
+use serde::Deserialize;
+use std::{
+    fs,
+    io::{Error, ErrorKind, Result},
+    path::{Path, PathBuf},
+};
+
+/// Rectangle in pixels on the field
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Rect {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+impl Rect {
+    /// Checks if a circle overlaps with the rectangle
+    pub fn intersects_circle(&self, x_center: f32, y_center: f32, radius: f32) -> bool {
+        let nearest_x = x_center.clamp(self.x as f32, (self.x + self.width) as f32);
+        let nearest_y = y_center.clamp(self.y as f32, (self.y + self.height) as f32);
+        (x_center - nearest_x).powi(2) + (y_center - nearest_y).powi(2) <= radius.powi(2)
+    }
+
+    fn fits_into(&self, width: u32, height: u32) -> bool {
+        self.x as u32 + self.width as u32 <= width && self.y as u32 + self.height as u32 <= height
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Point {
+    pub x: u16,
+    pub y: u16,
+}
+
+/// Box the points of a team are written into
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ScoreArea {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+    pub font_size: f32,
+}
+
+impl ScoreArea {
+    pub fn area(&self) -> Rect {
+        Rect {
+            x: self.x,
+            y: self.y,
+            width: self.width,
+            height: self.height,
+        }
+    }
+}
+
+/// Everything belonging to one half of the field
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Side {
+    /// When the ball touches this area, the team of the other side scores
+    pub goal: Rect,
+    /// Where the points of the team playing on this side are shown
+    pub score: ScoreArea,
+}
+
+/// Format of the field definition files, see `fields/default.toml`
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FieldDefinition {
+    /// Relative to the definition file
+    image: PathBuf,
+    /// Relative to the definition file. Red pixels are walls the ball bounces off, e.g. the goal posts.
+    hitbox: PathBuf,
+    /// Where the center of the ball is put at kick-off
+    kick_off: Point,
+    left: Side,
+    right: Side,
+}
+
+/// Maps coordinates of the field definition to coordinates on the screen. The field is scaled to fit the screen while
+/// keeping its aspect ratio and is centered, leaving bars at the sides that are not covered (letterboxing).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FieldTransform {
+    pub scale: f32,
+    pub x_offset: f32,
+    pub y_offset: f32,
+}
+
+impl FieldTransform {
+    pub const IDENTITY: FieldTransform = FieldTransform {
+        scale: 1.0,
+        x_offset: 0.0,
+        y_offset: 0.0,
+    };
+
+    pub fn fit(field_width: u16, field_height: u16, screen_width: u16, screen_height: u16) -> Self {
+        let scale = f32::min(
+            screen_width as f32 / field_width as f32,
+            screen_height as f32 / field_height as f32,
+        );
+        FieldTransform {
+            scale,
+            x_offset: ((screen_width as f32 - field_width as f32 * scale) / 2.0).floor(),
+            y_offset: ((screen_height as f32 - field_height as f32 * scale) / 2.0).floor(),
+        }
+    }
+
+    pub fn to_screen(&self, x: f32, y: f32) -> (f32, f32) {
+        (
+            x * self.scale + self.x_offset,
+            y * self.scale + self.y_offset,
+        )
+    }
+
+    fn point_to_screen(&self, point: Point) -> Point {
+        let (x, y) = self.to_screen(point.x as f32, point.y as f32);
+        Point {
+            x: x.round() as u16,
+            y: y.round() as u16,
+        }
+    }
+
+    fn rect_to_screen(&self, rect: Rect) -> Rect {
+        let (x, y) = self.to_screen(rect.x as f32, rect.y as f32);
+        Rect {
+            x: x.round() as u16,
+            y: y.round() as u16,
+            width: (rect.width as f32 * self.scale).round().max(1.0) as u16,
+            height: (rect.height as f32 * self.scale).round().max(1.0) as u16,
+        }
+    }
+}
+
+/// An arena the game can be played in, loaded from a field definition file
+pub struct FieldLayout {
+    pub image: DynamicImage,
+    pub hitbox: DynamicImage,
+    pub kick_off: Point,
+    pub left: Side,
+    pub right: Side,
+    /// How the coordinates of the definition file were mapped to the ones of this layout, see
+    /// [`fit_to_screen`][Self::fit_to_screen]
+    pub transform: FieldTransform,
+}
+
+impl FieldLayout {
+    pub fn load(path: &Path) -> Result<Self> {
+        let definition = fs::read_to_string(path).map_err(|err| {
+            Error::new(
+                err.kind(),
+                format!("Failed to read field definition {}: {err}", path.display()),
+            )
+        })?;
+        let definition: FieldDefinition = toml::from_str(&definition).map_err(|err| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("Failed to parse field definition {}: {err}", path.display()),
+            )
+        })?;
+
+        let directory = path.parent().unwrap_or(Path::new("."));
+        let image = load_image(&directory.join(&definition.image))?;
+        let hitbox = load_image(&directory.join(&definition.hitbox))?;
+
+        let (width, height) = image.dimensions();
+        let invalid = |message: String| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid field definition {}: {message}", path.display()),
+            )
+        };
+        if hitbox.dimensions() != (width, height) {
+            return Err(invalid(format!(
+                "The hitbox ({:?}) must have the same size as the field image ({:?})",
+                hitbox.dimensions(),
+                (width, height)
+            )));
+        }
+        let kick_off = definition.kick_off;
+        if kick_off.x as u32 >= width || kick_off.y as u32 >= height {
+            return Err(invalid(format!(
+                "The kick-off point {kick_off:?} is outside of the field"
+            )));
+        }
+        for (name, side) in [("left", &definition.left), ("right", &definition.right)] {
+            if !side.goal.fits_into(width, height) {
+                return Err(invalid(format!("The {name} goal is outside of the field")));
+            }
+            if !side.score.area().fits_into(width, height) {
+                return Err(invalid(format!("The {name} score is outside of the field")));
+            }
+        }
+
+        Ok(FieldLayout {
+            image,
+            hitbox,
+            kick_off,
+            left: definition.left,
+            right: definition.right,
+            transform: FieldTransform::IDENTITY,
+        })
+    }
+
+    /// Scales and letterboxes the field to the given screen size, so that all coordinates of the returned layout (and
+    /// the pixels of its images) are screen coordinates. The bars around the field become walls in the hitbox, so
+    /// the ball stays on the field.
+    pub fn fit_to_screen(self, screen_width: u16, screen_height: u16) -> Self {
+        if (self.width(), self.height()) == (screen_width, screen_height) {
+            return self;
+        }
+
+        let transform =
+            FieldTransform::fit(self.width(), self.height(), screen_width, screen_height);
+        println!(
+            "Fitting the field ({}x{}) to the screen ({screen_width}x{screen_height}) using {transform:?}",
+            self.width(),
+            self.height()
+        );
+
+        let scaled_width = (self.width() as f32 * transform.scale).round() as u32;
+        let scaled_height = (self.height() as f32 * transform.scale).round() as u32;
+        let letterbox = |image: &DynamicImage, filter: FilterType, background: Rgba<u8>| {
+            let scaled = imageops::resize(image, scaled_width, scaled_height, filter);
+            let mut screen =
+                RgbaImage::from_pixel(screen_width as u32, screen_height as u32, background);
+            imageops::replace(
+                &mut screen,
+                &scaled,
+                transform.x_offset as i64,
+                transform.y_offset as i64,
+            );
+            DynamicImage::ImageRgba8(screen)
+        };
+
+        let scale_side = |side: Side| {
+            let score = transform.rect_to_screen(side.score.area());
+            Side {
+                goal: transform.rect_to_screen(side.goal),
+                score: ScoreArea {
+                    x: score.x,
+                    y: score.y,
+                    width: score.width,
+                    height: score.height,
+                    font_size: side.score.font_size * transform.scale,
+                },
+            }
+        };
+
+        FieldLayout {
+            // Nothing is drawn onto the bars, so the players can use them
+            image: letterbox(&self.image, FilterType::Triangle, Rgba([0, 0, 0, 0])),
+            // The hitbox colors must not be blended, as only exact colors are matched
+            hitbox: letterbox(&self.hitbox, FilterType::Nearest, Rgba([255, 0, 0, 255])),
+            kick_off: transform.point_to_screen(self.kick_off),
+            left: scale_side(self.left),
+            right: scale_side(self.right),
+            transform,
+        }
+    }
+
+    pub fn width(&self) -> u16 {
+        self.image.width() as u16
+    }
+
+    pub fn height(&self) -> u16 {
+        self.image.height() as u16
+    }
+}
+
+fn load_image(path: &Path) -> Result<DynamicImage> {
+    let reader = ImageReader::open(path).map_err(|err| {
+        Error::new(
+            err.kind(),
+            format!("Failed to open field image {}: {err}", path.display()),
+        )
+    })?;
+    reader.decode().map_err(|err| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("Failed to decode field image {}: {err}", path.display()),
+        )
+    })
+}
@@ -1,67 +1,306 @@
 use std::{sync::Arc, time::Duration};
 
-use crate::{ball::Ball, client::Client, draw, field::Field, score::Score};
+use futures::future;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{
+    ball::{self, Ball, BallConfig, Touch},
+    canvas::{self, Canvas},
+    draw,
+    events::{self, Connection, Events, GameEvent},
+    field::Field,
+    field_layout::FieldLayout,
+    protocol::Encoding,
+    score::Score,
+    trap,
+    viewport::Viewport,
+};
 use tokio::{
-    io::Result,
+    io::{Error, ErrorKind, Result},
     time::{self, Instant},
 };
 
-pub struct Game {
-    client: Client,
-    field: Field,
-    ball: Ball,
-    score: Score,
+pub struct Game<C: Canvas> {
+    /// Used to open the connections for drawing
+    canvas: C,
+    field: Arc<Field>,
+    balls: Vec<Arc<Ball>>,
+    /// Every ball has its own connection for ticking, so that their pixel reads don't block each other
+    ball_canvases: Vec<C>,
+    score: Arc<Score>,
+    events: Events,
 }
 
-pub enum GoalScored {
+/// One of the two goals, named after the side of the field it is on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Goal {
     Left,
     Right,
 }
 
-impl Game {
-    pub async fn new(server_address: &str) -> Result<Self> {
-        let mut client = Client::new(server_address).await?;
-        let (screen_width, screen_height) = client.get_screen_size().await.unwrap();
+impl Goal {
+    pub fn defending_team(&self) -> Team {
+        match self {
+            Goal::Left => Team::Left,
+            Goal::Right => Team::Right,
+        }
+    }
+}
+
+/// The team playing on the given side of the field, i.e. defending the goal on that side
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Team {
+    Left,
+    Right,
+}
+
+impl Team {
+    pub fn opponent(&self) -> Team {
+        match self {
+            Team::Left => Team::Right,
+            Team::Right => Team::Left,
+        }
+    }
+}
+
+/// A ball went into a goal
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GoalScored {
+    /// The goal the ball went into
+    pub goal: Goal,
+    /// Gets the point. This is always the team attacking the goal, even for own goals.
+    pub scoring_team: Team,
+    /// The ball was last touched by the team defending the goal
+    pub own_goal: bool,
+    /// The last player the ball bounced off before going into the goal
+    pub last_touch: Option<Touch>,
+    /// The player the ball bounced off before the last touch, e.g. a pass to the player scoring the goal
+    pub assist: Option<Touch>,
+}
+
+impl GoalScored {
+    pub fn new(goal: Goal, last_touch: Option<Touch>, assist: Option<Touch>) -> Self {
+        let defending_team = goal.defending_team();
+        GoalScored {
+            goal,
+            scoring_team: defending_team.opponent(),
+            own_goal: last_touch.and_then(|touch| touch.team()) == Some(defending_team),
+            last_touch,
+            assist,
+        }
+    }
+}
+
+impl<C: Canvas + 'static> Game<C> {
+    /// When no `encoding` is given the best one supported by the server is picked.
+    /// All randomness (e.g. the kick-off directions) is derived from `seed`, so that a game can be reproduced.
+    /// Everything happening in the game is published on `events`, starting with the kick-off of every ball while
+    /// creating the game.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn new(
+        mut canvas: C,
+        encoding: Option<Encoding>,
+        field_layout: FieldLayout,
+        ball_config: BallConfig,
+        number_of_balls: u16,
+        seed: u64,
+        events: Events,
+    ) -> Result<Self> {
+        let mut rng = StdRng::seed_from_u64(seed);
+
+        let capabilities = canvas.probe_capabilities().await?;
+        println!("Server capabilities: {capabilities:?}");
+        if !capabilities.pixel_reads {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "The server does not support reading pixels (PX x y), which is needed to let the ball bounce off the players",
+            ));
+        }
+
+        let encoding = match encoding {
+            Some(Encoding::Binary) if !capabilities.binary => {
+                return Err(Error::new(
+                    ErrorKind::Unsupported,
+                    "Binary encoding was requested, but the server does not support the PB command",
+                ));
+            }
+            Some(encoding) => encoding,
+            None if capabilities.binary => Encoding::Binary,
+            None => Encoding::Ascii,
+        };
+        println!("Using {encoding:?} encoding");
+
+        // When the canvas is confined to a viewport, it only reports the size of the viewport
+        let (screen_width, screen_height) = canvas.get_screen_size().await?;
+        let viewport = canvas.viewport().unwrap_or(Viewport {
+            x: 0,
+            y: 0,
+            width: screen_width,
+            height: screen_height,
+        });
+
+        let field_layout = field_layout.fit_to_screen(viewport.width, viewport.height);
+        let ball_config = ball_config.scale(field_layout.transform.scale);
+        // Colors close to the player colors are easily overlooked when choosing a tolerance
+        for (name, image) in [
+            ("field", &field_layout.image),
+            ("ball", ball_config.image()),
+        ] {
+            let colors = ball_config.colors().matching_colors(image);
+            if !colors.is_empty() {
+                let examples: Vec<_> = colors
+                    .iter()
+                    .take(3)
+                    .map(|rgb| format!("{rgb:06x}"))
+                    .collect();
+                println!(
+                    "WARNING: {} colors of the {name} image count as player colors with the color tolerance {} (e.g. {}), the ball bounces off them",
+                    colors.len(),
+                    ball_config.colors().tolerance,
+                    examples.join(", ")
+                );
+            }
+        }
+        let field = Field::new(&field_layout.image, &viewport, encoding, &mut rng);
+        let score = Score::new(
+            encoding,
+            viewport,
+            field_layout.left.score,
+            field_layout.right.score,
+            StdRng::seed_from_u64(rng.gen()),
+            events.clone(),
+        )
+        .await;
+        let kick_off_points = kick_off_points(
+            &field_layout,
+            number_of_balls as usize,
+            ball_config.radius(),
+            &viewport,
+        );
+        let field_layout = Arc::new(field_layout);
+        let ball_config = Arc::new(ball_config);
+
+        let mut balls = Vec::with_capacity(number_of_balls as usize);
+        let mut ball_canvases = Vec::with_capacity(number_of_balls as usize);
+        for (id, kick_off) in kick_off_points.into_iter().enumerate() {
+            let ball = Ball::new(
+                id,
+                viewport,
+                encoding,
+                capabilities.offset,
+                Arc::clone(&ball_config),
+                Arc::clone(&field_layout),
+                kick_off,
+                StdRng::seed_from_u64(rng.gen()),
+                events.clone(),
+            )
+            .await?;
 
-        let ball = Ball::new(screen_width, screen_height).await?;
+            balls.push(Arc::new(ball));
+            ball_canvases.push(canvas.new_connection().await?);
+        }
 
         Ok(Game {
-            client,
-            field: Field::new(),
-            ball,
-            score: Score::new().await,
+            canvas,
+            field: Arc::new(field),
+            balls,
+            ball_canvases,
+            score: Arc::new(score),
+            events,
         })
     }
 
-    pub async fn start(mut self, server_address: &str, target_fps: u16) -> Result<()> {
-        let ball = Arc::new(self.ball);
-        let ball_2 = Arc::clone(&ball);
-        let field = Arc::new(self.field);
-        let score = Arc::new(self.score);
-        let score_2 = Arc::clone(&score);
+    pub fn balls(&self) -> &[Arc<Ball>] {
+        &self.balls
+    }
+
+    pub fn score(&self) -> &Score {
+        &self.score
+    }
+
+    /// Everything happening in the game is published here, e.g. to collect statistics
+    pub fn events(&self) -> &Events {
+        &self.events
+    }
+
+    /// Moves all balls by one tick, counts the touches and goals of the teams and lets the balls bounce off each other.
+    /// Returns the goals scored in this tick.
+    pub async fn tick(&mut self) -> Vec<GoalScored> {
+        // Every ball has its own connection, so the balls are ticked concurrently and their network latencies overlap
+        let events = &self.events;
+        let touches = future::join_all(
+            self.balls
+                .iter()
+                .zip(self.ball_canvases.iter_mut())
+                .enumerate()
+                .map(|(id, (ball, canvas))| async move {
+                    match ball.tick(canvas).await {
+                        Ok(touch) => touch,
+                        Err(err) => {
+                            events.publish(GameEvent::ConnectionError {
+                                connection: Connection::Ball(id),
+                                error: err.to_string(),
+                                reconnecting: err.is_connection_error(),
+                            });
+                            // The ball and score live outside of the connection, so they survive the reconnect
+                            if err.is_connection_error() {
+                                canvas.reconnect(Connection::Ball(id), events).await;
+                            }
+                            None
+                        }
+                    }
+                }),
+        )
+        .await;
 
-        let mut threads = Vec::new();
+        // Goals are handled in the order of the balls, so that the outcome does not depend on which tick finished first
+        let mut goals = Vec::new();
+        for (id, (ball, touch)) in self.balls.iter().zip(touches).enumerate() {
+            if let Some(touch) = touch {
+                self.score.count_touch(&touch).await;
+            }
+
+            // Only the ball that went into the goal gets reset, all others keep on playing
+            if let Some(goal) = ball.is_goal_scored() {
+                self.events.publish(GameEvent::Goal { ball: id, goal });
+                self.score.score_goal(goal).await;
+                ball.reset();
+                goals.push(goal);
+            }
+        }
+
+        ball::collide_balls(&self.balls);
+        goals
+    }
+
+    pub async fn start(mut self, target_fps: u16) -> Result<()> {
+        let mut threads = vec![tokio::spawn(events::log_events(self.events.subscribe()))];
 
         let mut fps_counter_last_update = Instant::now();
         let mut fps_counter = 0;
         let mut interval = time::interval(Duration::from_millis(1_000 / target_fps as u64));
         interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
 
+        for ball in &self.balls {
+            threads.extend(
+                draw::start_drawing(Arc::clone(ball), &self.canvas, 1, &self.events).await?,
+            );
+        }
+        threads.extend(
+            draw::start_drawing(Arc::clone(&self.field), &self.canvas, 1, &self.events).await?,
+        );
+        threads.extend(
+            draw::start_drawing(Arc::clone(&self.score), &self.canvas, 1, &self.events).await?,
+        );
+
         threads.push(tokio::spawn(async move {
             loop {
                 interval.tick().await;
 
-                // let start = Instant::now();
-                ball.tick(&mut self.client).await.unwrap();
-                // println!("Took {:?} to tick the ball", start.elapsed());
-
-                if let Some(goal) = ball.is_goal_scored() {
-                    score.score_goal(goal).await;
-                    ball.reset();
-                }
+                self.tick().await;
 
                 if fps_counter_last_update.elapsed() >= Duration::from_secs(1) {
-                    println!("{} fps", fps_counter);
+                    self.events.publish(GameEvent::Fps(fps_counter));
                     fps_counter = 0;
                     fps_counter_last_update = Instant::now();
                 } else {
@@ -70,10 +309,6 @@ impl Game {
             }
         }));
 
-        threads.extend(draw::start_drawing(ball_2, server_address, 1).await);
-        threads.extend(draw::start_drawing(field, server_address, 1).await);
-        threads.extend(draw::start_drawing(score_2, server_address, 1).await);
-
         for thread in threads {
             thread.await?;
         }
@@ -81,3 +316,63 @@ impl Game {
         Ok(())
     }
 }
+
+/// Spreads the kick-off points of the balls around the kick-off point of the field, so that they don't start on top of
+/// each other (where they would have no direction to bounce off to). The balls are lined up vertically, using further
+/// columns if they don't fit onto the field. Only spots on the field (not on the letterbox bars around it) that don't
+/// touch a wall of the hitbox are used.
+fn kick_off_points(
+    field_layout: &FieldLayout,
+    number_of_balls: usize,
+    radius: f32,
+    viewport: &Viewport,
+) -> Vec<(f32, f32)> {
+    let spacing = 2.5 * radius;
+    let transform = &field_layout.transform;
+    // The field is centered, so the bars on both sides have the same size
+    let (left, right) = (
+        transform.x_offset + radius,
+        viewport.width as f32 - transform.x_offset - radius,
+    );
+    let (top, bottom) = (
+        transform.y_offset + radius,
+        viewport.height as f32 - transform.y_offset - radius,
+    );
+    let (kick_off_x, kick_off_y) = (
+        field_layout.kick_off.x as f32,
+        field_layout.kick_off.y as f32,
+    );
+
+    // Alternates between both sides of the kick-off point: 0, 1, -1, 2, -2, ... The kick-off point does not need to be
+    // in the middle of the field, so twice the number of spots fitting onto the field are tried.
+    let offsets = |length: f32| {
+        let count = 2 * ((length / spacing).floor().max(0.0) as i32 + 1);
+        (0..count).map(|i| if i % 2 == 0 { -i / 2 } else { (i + 1) / 2 })
+    };
+    let touches_wall = |x: f32, y: f32| {
+        trap::free_spot_coordinates(x as i16, y as i16, radius, viewport.width, viewport.height)
+            .into_iter()
+            .any(|(x, y)| canvas::is_hitbox_wall(&field_layout.hitbox, x, y))
+    };
+
+    let mut points = Vec::with_capacity(number_of_balls);
+    for column in offsets(right - left) {
+        for row in offsets(bottom - top) {
+            if points.len() == number_of_balls {
+                return points;
+            }
+
+            let (x, y) = (
+                kick_off_x + column as f32 * spacing,
+                kick_off_y + row as f32 * spacing,
+            );
+            if (left..=right).contains(&x) && (top..=bottom).contains(&y) && !touches_wall(x, y) {
+                points.push((x, y));
+            }
+        }
+    }
+
+    // The field is full, the remaining balls bounce off each other once they start moving
+    points.resize(number_of_balls, (kick_off_x, kick_off_y));
+    points
+}
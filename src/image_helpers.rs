@@ -2,6 +2,7 @@ use crate::protocol::PixelflutRequest;
 
 use image::{DynamicImage, GenericImageView};
 use rusttype::{point, Font, Scale};
+use std::f32::consts::PI;
 
 pub const WHITE: u32 = 0x00ff_ffff;
 pub const BLACK: u32 = 0x0000_0000;
@@ -149,3 +150,46 @@ pub fn get_donut_coordinates(
 
     donut_coordinates
 }
+
+/// Same as [`get_donut_coordinates`], but only returns the coordinates within the sector of the donut that spans
+/// `sector_angle` (in radians) and is centered around `direction`. A `sector_angle` of 2π or more returns the whole donut.
+#[allow(clippy::too_many_arguments)]
+pub fn get_arc_coordinates(
+    x_center: i16,
+    y_center: i16,
+    inner_circle_radius: f32,
+    outer_circle_radius: f32,
+    direction: f32,
+    sector_angle: f32,
+    screen_width: u16,
+    screen_height: u16,
+) -> Vec<(u16, u16)> {
+    let mut donut_coordinates = get_donut_coordinates(
+        x_center,
+        y_center,
+        inner_circle_radius,
+        outer_circle_radius,
+        screen_width,
+        screen_height,
+    );
+    if sector_angle >= 2.0 * PI {
+        return donut_coordinates;
+    }
+
+    donut_coordinates.retain(|(x, y)| {
+        let x_rel = (*x as i16 - x_center) as f32;
+        let y_rel = (*y as i16 - y_center) as f32;
+        angle_difference(y_rel.atan2(x_rel), direction).abs() <= sector_angle / 2.0
+    });
+    donut_coordinates
+}
+
+/// Returns the difference between the two angles (in radians) normalized to -π..=π
+pub fn angle_difference(a: f32, b: f32) -> f32 {
+    let difference = (a - b).rem_euclid(2.0 * PI);
+    if difference > PI {
+        difference - 2.0 * PI
+    } else {
+        difference
+    }
+}
@@ -0,0 +1,18 @@
+pub mod args;
+pub mod ball;
+pub mod canvas;
+pub mod client;
+pub mod draw;
+pub mod events;
+pub mod field;
+pub mod field_layout;
+pub mod game;
+pub mod image_helpers;
+pub mod memory_canvas;
+pub mod player_colors;
+pub mod probe;
+pub mod protocol;
+pub mod score;
+pub mod server;
+pub mod trap;
+pub mod viewport;
@@ -1,24 +1,69 @@
-use crate::args::Args;
 use clap::Parser;
-use game::Game;
+use pixel_soccer::{
+    args::Args,
+    ball::{BallConfig, BallPhysics},
+    client::Client,
+    events::Events,
+    field_layout::FieldLayout,
+    game::Game,
+    player_colors::PlayerColors,
+    probe::ProbeConfig,
+    trap::TrapConfig,
+};
+use rand::Rng;
 use tokio::io::Result;
 
-mod args;
-mod ball;
-mod client;
-mod draw;
-mod field;
-mod game;
-mod image_helpers;
-mod protocol;
-mod score;
-
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
 
-    let game = Game::new(&args.server_address).await?;
-    game.start(&args.server_address, args.fps).await?;
+    let probe = ProbeConfig {
+        sector_angle: (args.probe_angle as f32).to_radians(),
+        sampling: args.probe_sampling,
+    };
+    let physics = BallPhysics {
+        speed: args.ball_speed,
+        min_speed: args.ball_min_speed.unwrap_or(args.ball_speed),
+        max_speed: args.ball_max_speed.unwrap_or(args.ball_speed),
+        friction: args.ball_friction,
+        hit_acceleration: args.ball_hit_acceleration,
+        max_step: args.ball_max_step,
+    };
+    let trap = TrapConfig {
+        policy: args.trap_policy,
+        ticks: args.trap_ticks,
+        ratio: args.trap_ratio,
+    };
+    let colors = PlayerColors {
+        shared: args.player_colors,
+        left: args.left_team_colors,
+        right: args.right_team_colors,
+        tolerance: args.color_tolerance,
+    };
+    let ball_config = BallConfig::new(
+        &args.ball_image,
+        args.ball_radius,
+        physics,
+        probe,
+        trap,
+        colors,
+    )?;
+    let seed = args.seed.unwrap_or_else(|| rand::thread_rng().gen());
+    println!("Using seed {seed}, pass --seed {seed} to reproduce this game");
+
+    let client = Client::new(&args.server_address, args.viewport).await?;
+    let field_layout = FieldLayout::load(&args.field)?;
+    let game = Game::new(
+        client,
+        args.encoding,
+        field_layout,
+        ball_config,
+        args.balls,
+        seed,
+        Events::new(),
+    )
+    .await?;
+    game.start(args.fps).await?;
 
     Ok(())
 }
@@ -0,0 +1,159 @@
+use async_trait::async_trait;
+use image::{Rgba, RgbaImage};
+use std::{
+    collections::VecDeque,
+    io,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    canvas::Canvas,
+    events::{Connection, Events, GameEvent},
+    protocol::{PixelflutRequest, ProtocolError, ServerCapabilities},
+};
+
+/// A [`Canvas`] backed by an image in memory instead of a Pixelflut server, so that the game can run without any
+/// network, e.g. in tests. All connections opened using [`new_connection`][Canvas::new_connection] share the same
+/// image, but (same as with a server) have their own offset and pending pixel reads.
+pub struct MemoryCanvas {
+    image: Arc<Mutex<RgbaImage>>,
+    offset: (u16, u16),
+    /// Responses to the pixel reads, in the order they were requested
+    pending_pixels: VecDeque<(u16, u16, u32)>,
+    /// Bytes of a request that was only partially written so far
+    partial_request: Vec<u8>,
+}
+
+impl MemoryCanvas {
+    /// Creates a black canvas with the given size
+    pub fn new(width: u16, height: u16) -> Self {
+        Self::from_image(RgbaImage::from_pixel(
+            width as u32,
+            height as u32,
+            Rgba([0, 0, 0, 255]),
+        ))
+    }
+
+    pub fn from_image(image: RgbaImage) -> Self {
+        MemoryCanvas {
+            image: Arc::new(Mutex::new(image)),
+            offset: (0, 0),
+            pending_pixels: VecDeque::new(),
+            partial_request: Vec::new(),
+        }
+    }
+
+    /// The image shared by all connections, e.g. to paint players onto it or to inspect what was drawn
+    pub fn image(&self) -> Arc<Mutex<RgbaImage>> {
+        Arc::clone(&self.image)
+    }
+
+    /// Executes a single request against the image
+    fn execute(&mut self, image: &mut RgbaImage, request: PixelflutRequest) {
+        match request {
+            PixelflutRequest::SetPixel { x, y, rgb } => {
+                let (x, y) = (
+                    x as u32 + self.offset.0 as u32,
+                    y as u32 + self.offset.1 as u32,
+                );
+                if x < image.width() && y < image.height() {
+                    let [_, r, g, b] = rgb.to_be_bytes();
+                    image.put_pixel(x, y, Rgba([r, g, b, 255]));
+                }
+            }
+            PixelflutRequest::GetPixel { x, y } => {
+                let (x, y) = (
+                    x.saturating_add(self.offset.0),
+                    y.saturating_add(self.offset.1),
+                );
+                // Pixels outside of the image are ignored, same as most servers do
+                if let Some(pixel) = image.get_pixel_checked(x as u32, y as u32) {
+                    let [r, g, b, _] = pixel.0;
+                    self.pending_pixels
+                        .push_back((x, y, u32::from_be_bytes([0, r, g, b])));
+                }
+            }
+            PixelflutRequest::SetOffset { x, y } => self.offset = (x, y),
+            // The size and capabilities are only asked for using get_screen_size and probe_capabilities, which don't go
+            // through here
+            PixelflutRequest::Help | PixelflutRequest::GetSize => (),
+        }
+    }
+}
+
+#[async_trait]
+impl Canvas for MemoryCanvas {
+    async fn new_connection(&self) -> io::Result<Self> {
+        Ok(MemoryCanvas {
+            image: Arc::clone(&self.image),
+            offset: (0, 0),
+            pending_pixels: VecDeque::new(),
+            partial_request: Vec::new(),
+        })
+    }
+
+    /// The image can not go away, so there is nothing to reconnect to
+    async fn reconnect(&mut self, connection: Connection, events: &Events) {
+        events.publish(GameEvent::Reconnected { connection });
+    }
+
+    async fn get_screen_size(&mut self) -> Result<(u16, u16), ProtocolError> {
+        let (width, height) = self.image.lock().unwrap().dimensions();
+        Ok((width as u16, height as u16))
+    }
+
+    async fn probe_capabilities(&mut self) -> Result<ServerCapabilities, ProtocolError> {
+        Ok(ServerCapabilities {
+            binary: true,
+            offset: true,
+            alpha: false,
+            pixel_reads: true,
+            get_rect: false,
+        })
+    }
+
+    async fn write_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let mut buffer = std::mem::take(&mut self.partial_request);
+        buffer.extend_from_slice(bytes);
+
+        {
+            let image = Arc::clone(&self.image);
+            let mut image = image.lock().unwrap();
+            let mut position = 0;
+            while let Some((request, length)) = PixelflutRequest::parse(&buffer[position..]) {
+                position += length;
+                // Same as a server, we simply ignore requests we don't understand
+                if let Ok(request) = request {
+                    self.execute(&mut image, request);
+                }
+            }
+            buffer.drain(..position);
+        }
+        self.partial_request = buffer;
+
+        // Nothing here waits for the network, so drawing in a loop would otherwise never give other tasks a chance to
+        // run
+        tokio::task::yield_now().await;
+        Ok(())
+    }
+
+    async fn request_pixels(&mut self, coordinates: &[(u16, u16)]) -> io::Result<()> {
+        let image = Arc::clone(&self.image);
+        let mut image = image.lock().unwrap();
+        for &(x, y) in coordinates {
+            self.execute(&mut image, PixelflutRequest::GetPixel { x, y });
+        }
+        Ok(())
+    }
+
+    async fn read_pixel(&mut self) -> Result<(u16, u16, u32), ProtocolError> {
+        // Waiting for a response that never comes would block forever
+        self.pending_pixels.pop_front().ok_or_else(|| {
+            ProtocolError::UnexpectedResponse("no pixel read is pending".to_string())
+        })
+    }
+
+    fn connection_id(&self) -> u64 {
+        0
+    }
+}
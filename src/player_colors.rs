@@ -0,0 +1,216 @@
+use image::DynamicImage;
+use std::{fmt::Display, str::FromStr};
+
+use crate::game::Team;
+
+/// Who painted a pixel the ball bounced off, see [`PlayerColors::classify`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Player {
+    /// Painted with a color that does not belong to any team
+    Anyone,
+    Team(Team),
+}
+
+/// Decides which pixels on the screen are players the ball bounces off. Players draw with all kind of tools, so
+/// besides the exact colors also similar ones (e.g. `fe0000` or anti-aliased red) can be accepted, see
+/// [`ColorTolerance`].
+#[derive(Clone, Debug)]
+pub struct PlayerColors {
+    /// Colors any player can use
+    pub shared: Vec<u32>,
+    /// Colors only used by the team playing on the left side, so that touches can be attributed to it
+    pub left: Vec<u32>,
+    /// Colors only used by the team playing on the right side
+    pub right: Vec<u32>,
+    pub tolerance: ColorTolerance,
+}
+
+impl PlayerColors {
+    /// Returns the player a pixel belongs to, or `None` if the ball does not bounce off it.
+    /// The team colors take precedence over the shared ones.
+    pub fn classify(&self, rgb: u32) -> Option<Player> {
+        // rgb values have the upper byte set to 0, everything else (e.g. the field hitbox) is not a player
+        if rgb > 0x00ff_ffff {
+            return None;
+        }
+
+        if self.matches_any(&self.left, rgb) {
+            Some(Player::Team(Team::Left))
+        } else if self.matches_any(&self.right, rgb) {
+            Some(Player::Team(Team::Right))
+        } else if self.matches_any(&self.shared, rgb) {
+            Some(Player::Anyone)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the colors of the drawn (not transparent) pixels of `image` that count as players, e.g. to warn about
+    /// field lines the ball would bounce off. Every color is only returned once.
+    pub fn matching_colors(&self, image: &DynamicImage) -> Vec<u32> {
+        let converted;
+        let image = match image.as_rgba8() {
+            Some(image) => image,
+            None => {
+                converted = image.to_rgba8();
+                &converted
+            }
+        };
+
+        let mut colors = Vec::new();
+        for pixel in image.pixels() {
+            let [r, g, b, alpha] = pixel.0;
+            let rgb = u32::from_be_bytes([0, r, g, b]);
+            // Skip runs of the same color early, images mostly consist of big areas with a single color
+            if alpha != 0 && colors.last() != Some(&rgb) {
+                colors.push(rgb);
+            }
+        }
+        colors.sort_unstable();
+        colors.dedup();
+        colors.retain(|rgb| self.classify(*rgb).is_some());
+        colors
+    }
+
+    fn matches_any(&self, colors: &[u32], rgb: u32) -> bool {
+        colors
+            .iter()
+            .any(|color| self.tolerance.matches(*color, rgb))
+    }
+}
+
+/// How much a pixel may differ from a player color to still count as that color
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColorTolerance {
+    Exact,
+    /// Maximum euclidean distance in RGB space, where every channel ranges from 0 to 255
+    Rgb(f32),
+    /// Maximum difference of each component in HSV space. The hue is given in degrees, the saturation and value range
+    /// from 0 to 1.
+    Hsv {
+        hue: f32,
+        saturation: f32,
+        value: f32,
+    },
+}
+
+impl ColorTolerance {
+    pub fn matches(&self, color: u32, rgb: u32) -> bool {
+        if color == rgb {
+            return true;
+        }
+
+        match *self {
+            ColorTolerance::Exact => false,
+            ColorTolerance::Rgb(distance) => {
+                let squared_distance: f32 = channels(color)
+                    .iter()
+                    .zip(channels(rgb))
+                    .map(|(a, b)| (a - b).powi(2))
+                    .sum();
+                squared_distance <= distance.powi(2)
+            }
+            ColorTolerance::Hsv {
+                hue,
+                saturation,
+                value,
+            } => {
+                let (color_hue, color_saturation, color_value) = to_hsv(color);
+                let (rgb_hue, rgb_saturation, rgb_value) = to_hsv(rgb);
+                let hue_difference = (color_hue - rgb_hue).abs() % 360.0;
+                hue_difference.min(360.0 - hue_difference) <= hue
+                    && (color_saturation - rgb_saturation).abs() <= saturation
+                    && (color_value - rgb_value).abs() <= value
+            }
+        }
+    }
+}
+
+impl FromStr for ColorTolerance {
+    type Err = String;
+
+    /// Parses `exact`, `rgb:<distance>` or `hsv:<hue>,<saturation>,<value>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "Invalid color tolerance {s:?}, expected one of \"exact\", \"rgb:<distance>\" or \"hsv:<hue>,<saturation>,<value>\""
+            )
+        };
+        let parse_value = |value: &str| {
+            value
+                .trim()
+                .parse::<f32>()
+                .ok()
+                .filter(|value| *value >= 0.0)
+                .ok_or_else(|| {
+                    format!("Invalid tolerance {value:?}, must be a non-negative number")
+                })
+        };
+
+        match s.split_once(':') {
+            None if s == "exact" => Ok(ColorTolerance::Exact),
+            Some(("rgb", distance)) => Ok(ColorTolerance::Rgb(parse_value(distance)?)),
+            Some(("hsv", values)) => match values.split(',').collect::<Vec<_>>()[..] {
+                [hue, saturation, value] => Ok(ColorTolerance::Hsv {
+                    hue: parse_value(hue)?,
+                    saturation: parse_value(saturation)?,
+                    value: parse_value(value)?,
+                }),
+                _ => Err(invalid()),
+            },
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl Display for ColorTolerance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ColorTolerance::Exact => write!(f, "exact"),
+            ColorTolerance::Rgb(distance) => write!(f, "rgb:{distance}"),
+            ColorTolerance::Hsv {
+                hue,
+                saturation,
+                value,
+            } => write!(f, "hsv:{hue},{saturation},{value}"),
+        }
+    }
+}
+
+/// Parses a color given as hex rgb value, e.g. `ff0000` or `#ff0000`
+pub fn parse_color(s: &str) -> Result<u32, String> {
+    let hex = s.trim().trim_start_matches('#');
+    // from_str_radix would also accept a sign, e.g. "+fffff"
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!(
+            "Invalid color {s:?}, expected 6 hex digits like \"ff0000\""
+        ));
+    }
+    u32::from_str_radix(hex, 16).map_err(|err| format!("Invalid color {s:?}: {err}"))
+}
+
+fn channels(rgb: u32) -> [f32; 3] {
+    let [_, r, g, b] = rgb.to_be_bytes();
+    [r as f32, g as f32, b as f32]
+}
+
+/// Returns the hue in degrees, the saturation and the value
+fn to_hsv(rgb: u32) -> (f32, f32, f32) {
+    let [r, g, b] = channels(rgb).map(|channel| channel / 255.0);
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+
+    let hue = if delta == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+    let saturation = if max == 0.0 { 0.0 } else { delta / max };
+
+    (hue, saturation, max)
+}
@@ -0,0 +1,172 @@
+use std::{f32::consts::PI, fmt::Display, str::FromStr};
+
+use crate::image_helpers::{angle_difference, get_arc_coordinates};
+
+/// Describes which pixels around the ball are read to detect collisions
+#[derive(Clone, Copy, Debug)]
+pub struct ProbeConfig {
+    /// Angle (in radians) of the sector in front of the ball that is checked
+    pub sector_angle: f32,
+    pub sampling: ProbeSampling,
+}
+
+impl ProbeConfig {
+    /// Returns the pixels to read for a ball at the given center moving into `direction`
+    #[allow(clippy::too_many_arguments)]
+    pub fn coordinates(
+        &self,
+        x_center: i16,
+        y_center: i16,
+        inner_circle_radius: f32,
+        outer_circle_radius: f32,
+        direction: f32,
+        screen_width: u16,
+        screen_height: u16,
+    ) -> Vec<(u16, u16)> {
+        self.sampling.coordinates(
+            x_center,
+            y_center,
+            inner_circle_radius,
+            outer_circle_radius,
+            direction,
+            self.sector_angle,
+            screen_width,
+            screen_height,
+        )
+    }
+}
+
+/// Decides which pixels around the ball are read to detect collisions.
+/// Reading less pixels allows for higher fps on high-latency servers, at the cost of missing thin lines.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProbeSampling {
+    /// Every pixel of the ring
+    Full,
+    /// Only every nth pixel of the ring, ordered by their angle
+    EveryNth(usize),
+    /// The given number of rays, evenly distributed and going from the inner to the outer circle
+    Rays(usize),
+}
+
+impl ProbeSampling {
+    /// Returns the coordinates to read within the sector of the donut that spans `sector_angle` (in radians) and is
+    /// centered around `direction`. See [`get_arc_coordinates`] for details.
+    #[allow(clippy::too_many_arguments)]
+    pub fn coordinates(
+        &self,
+        x_center: i16,
+        y_center: i16,
+        inner_circle_radius: f32,
+        outer_circle_radius: f32,
+        direction: f32,
+        sector_angle: f32,
+        screen_width: u16,
+        screen_height: u16,
+    ) -> Vec<(u16, u16)> {
+        match self {
+            ProbeSampling::Full => get_arc_coordinates(
+                x_center,
+                y_center,
+                inner_circle_radius,
+                outer_circle_radius,
+                direction,
+                sector_angle,
+                screen_width,
+                screen_height,
+            ),
+            ProbeSampling::EveryNth(n) => {
+                let mut coordinates = get_arc_coordinates(
+                    x_center,
+                    y_center,
+                    inner_circle_radius,
+                    outer_circle_radius,
+                    direction,
+                    sector_angle,
+                    screen_width,
+                    screen_height,
+                );
+                // Sort by angle, so that the remaining pixels are evenly distributed around the ring
+                let angle = |(x, y): &(u16, u16)| {
+                    let x_rel = (*x as i16 - x_center) as f32;
+                    let y_rel = (*y as i16 - y_center) as f32;
+                    angle_difference(y_rel.atan2(x_rel), direction)
+                };
+                coordinates.sort_by(|a, b| angle(a).total_cmp(&angle(b)));
+                coordinates.into_iter().step_by(*n).collect()
+            }
+            ProbeSampling::Rays(rays) => {
+                let sector_angle = sector_angle.min(2.0 * PI);
+                let mut coordinates = Vec::new();
+                for ray in 0..*rays {
+                    let ray_dir = direction - sector_angle / 2.0
+                        + (ray as f32 + 0.5) * sector_angle / *rays as f32;
+                    let mut distance = inner_circle_radius.max(0.0);
+                    while distance <= outer_circle_radius {
+                        let x = x_center as f32 + distance * ray_dir.cos();
+                        let y = y_center as f32 + distance * ray_dir.sin();
+                        // Rounding could leave the ring, which would exceed the area read by the client
+                        let (x_rel, y_rel) =
+                            (x.round() - x_center as f32, y.round() - y_center as f32);
+                        let rounded_distance = f32::sqrt(x_rel.powi(2) + y_rel.powi(2));
+                        if x.round() >= 0.0
+                            && x.round() < screen_width as f32
+                            && y.round() >= 0.0
+                            && y.round() < screen_height as f32
+                            && rounded_distance >= inner_circle_radius
+                            && rounded_distance < outer_circle_radius
+                        {
+                            let coordinate = (x.round() as u16, y.round() as u16);
+                            if !coordinates.contains(&coordinate) {
+                                coordinates.push(coordinate);
+                            }
+                        }
+                        distance += 1.0;
+                    }
+                }
+                coordinates
+            }
+        }
+    }
+}
+
+impl FromStr for ProbeSampling {
+    type Err = String;
+
+    /// Parses `full`, `every-nth:<n>` or `rays:<n>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, count) = match s.split_once(':') {
+            Some((name, count)) => (
+                name,
+                Some(
+                    count
+                        .parse::<usize>()
+                        .ok()
+                        .filter(|count| *count > 0)
+                        .ok_or_else(|| {
+                            format!("Invalid count {count:?}, must be a positive number")
+                        })?,
+                ),
+            ),
+            None => (s, None),
+        };
+
+        match (name, count) {
+            ("full", None) => Ok(ProbeSampling::Full),
+            ("every-nth", Some(n)) => Ok(ProbeSampling::EveryNth(n)),
+            ("rays", Some(rays)) => Ok(ProbeSampling::Rays(rays)),
+            _ => Err(format!(
+                "Invalid probe sampling {s:?}, expected one of \"full\", \"every-nth:<n>\" or \"rays:<n>\""
+            )),
+        }
+    }
+}
+
+impl Display for ProbeSampling {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProbeSampling::Full => write!(f, "full"),
+            ProbeSampling::EveryNth(n) => write!(f, "every-nth:{n}"),
+            ProbeSampling::Rays(rays) => write!(f, "rays:{rays}"),
+        }
+    }
+}
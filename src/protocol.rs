@@ -1,7 +1,34 @@
 use async_trait::async_trait;
+use clap::ValueEnum;
+use std::{
+    fmt::Display,
+    io::{self, Write},
+};
+
+/// Wire format used to send [`PixelflutRequest::SetPixel`] commands to the server.
+/// All other commands are always sent as ASCII.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Encoding {
+    /// `PX x y rrggbb\n`, understood by every Pixelflut server
+    Ascii,
+    /// `PB` followed by x and y as little-endian u16 and the color as rgba (10 bytes in total).
+    /// Supported by e.g. breakwater.
+    Binary,
+}
+
+impl Encoding {
+    /// Average number of bytes a single set pixel command takes, useful to pre-allocate buffers
+    pub fn avg_bytes_per_pixel_set_command(&self) -> usize {
+        match self {
+            Encoding::Ascii => "PX 123 123 ffffff\n".len(),
+            Encoding::Binary => "PBxxyyrgba".len(),
+        }
+    }
+}
 
 #[derive(Debug)]
 pub enum PixelflutRequest {
+    Help,
     GetSize,
     /// Layout of rgb: 8 bits padding, 8 bits r, 8 bits g, 8 bits green
     SetPixel {
@@ -13,6 +40,11 @@ pub enum PixelflutRequest {
         x: u16,
         y: u16,
     },
+    /// Adds the offset to the coordinates of all following commands on the same connection
+    SetOffset {
+        x: u16,
+        y: u16,
+    },
 }
 
 #[derive(Debug)]
@@ -29,21 +61,246 @@ pub enum PixelflutResponse {
     },
 }
 
+#[derive(Debug)]
+pub enum ProtocolError {
+    /// The server sent a response we did not expect, e.g. an unknown command or a pixel we did not ask for
+    UnexpectedResponse(String),
+    MalformedCoordinate(String),
+    MalformedColor(String),
+    /// A request that can not be parsed, e.g. an unknown command
+    MalformedRequest(String),
+    /// The server closed the connection
+    Eof,
+    Io(io::Error),
+}
+
+impl Display for ProtocolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProtocolError::UnexpectedResponse(response) => {
+                write!(f, "unexpected response from server: {response:?}")
+            }
+            ProtocolError::MalformedCoordinate(response) => {
+                write!(f, "malformed coordinate in response {response:?}")
+            }
+            ProtocolError::MalformedColor(response) => {
+                write!(f, "malformed color in response {response:?}")
+            }
+            ProtocolError::MalformedRequest(request) => write!(f, "malformed request {request:?}"),
+            ProtocolError::Eof => write!(f, "server closed the connection"),
+            ProtocolError::Io(err) => write!(f, "io error: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+impl ProtocolError {
+    /// Returns true if the connection is broken and needs to be re-established.
+    /// For all other errors the connection is still in sync and can be used further.
+    pub fn is_connection_error(&self) -> bool {
+        matches!(self, ProtocolError::Eof | ProtocolError::Io(_))
+    }
+}
+
+impl From<io::Error> for ProtocolError {
+    fn from(err: io::Error) -> Self {
+        ProtocolError::Io(err)
+    }
+}
+
+impl From<ProtocolError> for io::Error {
+    fn from(err: ProtocolError) -> Self {
+        match err {
+            ProtocolError::Io(err) => err,
+            ProtocolError::Eof => io::Error::new(io::ErrorKind::UnexpectedEof, err.to_string()),
+            _ => io::Error::new(io::ErrorKind::InvalidData, err.to_string()),
+        }
+    }
+}
+
+impl PixelflutResponse {
+    /// Parses a single line (with or without the trailing newline) sent by the server
+    pub fn parse(line: &str) -> Result<Self, ProtocolError> {
+        let mut parts = line.split_whitespace();
+        match parts.next() {
+            Some("PX") => {
+                let x = parse_coordinate(parts.next(), line)?;
+                let y = parse_coordinate(parts.next(), line)?;
+                let rgb = parse_color(parts.next(), line)?;
+                Ok(PixelflutResponse::Pixel { x, y, rgb })
+            }
+            Some("SIZE") => {
+                let width = parse_coordinate(parts.next(), line)?;
+                let height = parse_coordinate(parts.next(), line)?;
+                Ok(PixelflutResponse::Size { width, height })
+            }
+            None | Some(_) => Err(ProtocolError::UnexpectedResponse(line.to_owned())),
+        }
+    }
+}
+
+impl PixelflutRequest {
+    /// Parses the first request at the start of `buffer`, as sent by a client.
+    /// Returns the result together with the number of bytes the request took, so that the caller can skip over it even
+    /// if it is malformed. Returns None if `buffer` does not contain a complete request yet.
+    pub fn parse(buffer: &[u8]) -> Option<(Result<Self, ProtocolError>, usize)> {
+        if buffer.starts_with(b"PB") {
+            let command = buffer.get(..10)?;
+            let x = u16::from_le_bytes([command[2], command[3]]);
+            let y = u16::from_le_bytes([command[4], command[5]]);
+            // The alpha channel is dropped, as we don't support blending
+            let rgb = u32::from_be_bytes([0, command[6], command[7], command[8]]);
+            return Some((Ok(PixelflutRequest::SetPixel { x, y, rgb }), 10));
+        }
+
+        let line_length = buffer.iter().position(|byte| *byte == b'\n')? + 1;
+        let line = String::from_utf8_lossy(&buffer[..line_length]);
+        Some((Self::parse_line(&line), line_length))
+    }
+
+    fn parse_line(line: &str) -> Result<Self, ProtocolError> {
+        let malformed = || ProtocolError::MalformedRequest(line.to_owned());
+        let mut parts = line.split_whitespace();
+        let request = match parts.next() {
+            Some("PX") => {
+                let x = parse_coordinate(parts.next(), line).map_err(|_| malformed())?;
+                let y = parse_coordinate(parts.next(), line).map_err(|_| malformed())?;
+                match parts.next() {
+                    None => PixelflutRequest::GetPixel { x, y },
+                    color => {
+                        let rgb = parse_color(color, line).map_err(|_| malformed())?;
+                        PixelflutRequest::SetPixel { x, y, rgb }
+                    }
+                }
+            }
+            Some("HELP") => PixelflutRequest::Help,
+            Some("SIZE") => PixelflutRequest::GetSize,
+            Some("OFFSET") => {
+                let x = parse_coordinate(parts.next(), line).map_err(|_| malformed())?;
+                let y = parse_coordinate(parts.next(), line).map_err(|_| malformed())?;
+                PixelflutRequest::SetOffset { x, y }
+            }
+            None | Some(_) => return Err(malformed()),
+        };
+
+        match parts.next() {
+            Some(_) => Err(malformed()),
+            None => Ok(request),
+        }
+    }
+}
+
+fn parse_coordinate(part: Option<&str>, line: &str) -> Result<u16, ProtocolError> {
+    part.and_then(|part| part.parse::<u16>().ok())
+        .ok_or_else(|| ProtocolError::MalformedCoordinate(line.to_owned()))
+}
+
+/// Accepts `rrggbb` as well as `rrggbbaa`, in the latter case the alpha channel is dropped
+fn parse_color(part: Option<&str>, line: &str) -> Result<u32, ProtocolError> {
+    let malformed = || ProtocolError::MalformedColor(line.to_owned());
+    let part = part.ok_or_else(malformed)?;
+    // from_str_radix would also accept a sign, e.g. "+fffff"
+    if !part.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(malformed());
+    }
+    let rgba = u32::from_str_radix(part, 16).map_err(|_| malformed())?;
+    match part.len() {
+        6 => Ok(rgba),
+        8 => Ok(rgba >> 8),
+        _ => Err(malformed()),
+    }
+}
+
 #[async_trait]
 pub trait Serialize {
-    fn serialize(&self, vec: &mut Vec<u8>);
+    fn serialize(&self, vec: &mut Vec<u8>, encoding: Encoding);
 }
 
 impl Serialize for PixelflutRequest {
-    fn serialize(&self, vec: &mut Vec<u8>) {
+    fn serialize(&self, vec: &mut Vec<u8>, encoding: Encoding) {
         match self {
+            PixelflutRequest::Help => vec.extend_from_slice("HELP\n".as_bytes()),
             PixelflutRequest::GetSize => vec.extend_from_slice("SIZE\n".as_bytes()),
-            PixelflutRequest::SetPixel { x, y, rgb } => {
-                vec.extend_from_slice(format!("PX {x} {y} {rgb:06x}\n").as_bytes())
+            // Writing into a Vec can not fail. We write directly into it instead of using format!, to not allocate a
+            // String per command
+            PixelflutRequest::SetPixel { x, y, rgb } => match encoding {
+                Encoding::Ascii => writeln!(vec, "PX {x} {y} {rgb:06x}").unwrap(),
+                Encoding::Binary => {
+                    vec.extend_from_slice("PB".as_bytes());
+                    vec.extend_from_slice(&x.to_le_bytes());
+                    vec.extend_from_slice(&y.to_le_bytes());
+                    // rgb is stored as 0x00rrggbb, so after shifting big-endian gives us r, g, b, a
+                    vec.extend_from_slice(&(rgb << 8 | 0xff).to_be_bytes());
+                }
+            },
+            PixelflutRequest::GetPixel { x, y } => writeln!(vec, "PX {x} {y}").unwrap(),
+            PixelflutRequest::SetOffset { x, y } => writeln!(vec, "OFFSET {x} {y}").unwrap(),
+        }
+    }
+}
+
+/// Responses are always sent as ASCII, so `encoding` is ignored
+impl Serialize for PixelflutResponse {
+    fn serialize(&self, vec: &mut Vec<u8>, _encoding: Encoding) {
+        match self {
+            PixelflutResponse::Size { width, height } => {
+                writeln!(vec, "SIZE {width} {height}").unwrap()
+            }
+            PixelflutResponse::Pixel { x, y, rgb } => {
+                writeln!(vec, "PX {x} {y} {rgb:06x}").unwrap()
+            }
+        }
+    }
+}
+
+/// Features a server supports on top of the basic `SIZE` and `PX x y rrggbb` commands.
+/// Determined by [`Canvas::probe_capabilities`][crate::canvas::Canvas::probe_capabilities].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ServerCapabilities {
+    /// `PB` binary set pixel command, see [`Encoding::Binary`]
+    pub binary: bool,
+    /// `OFFSET x y` command
+    pub offset: bool,
+    /// Colors with alpha channel (`PX x y rrggbbaa`)
+    pub alpha: bool,
+    /// Reading pixels using `PX x y`
+    pub pixel_reads: bool,
+    /// Reading a whole rectangle with a single command (e.g. `GETRECT`)
+    pub get_rect: bool,
+}
+
+impl ServerCapabilities {
+    /// Parses the text the server returned for a `HELP` command.
+    /// As there is no standardized format, we only look for the command names in the text.
+    /// `pixel_reads` can not be parsed reliably from the help text, so it needs to be tested by the caller.
+    pub fn from_help_text(help_text: &str, pixel_reads: bool) -> Self {
+        let mut capabilities = ServerCapabilities {
+            pixel_reads,
+            ..Default::default()
+        };
+
+        for line in help_text.lines() {
+            let line = line.trim().to_uppercase();
+            let command = line
+                .split(|c: char| c.is_whitespace() || c == ':')
+                .next()
+                .unwrap_or_default();
+
+            if command.starts_with("PB") {
+                capabilities.binary = true;
+            }
+            if command == "OFFSET" {
+                capabilities.offset = true;
             }
-            PixelflutRequest::GetPixel { x, y } => {
-                vec.extend_from_slice(format!("PX {x} {y}\n").as_bytes())
+            if command.contains("RECT") {
+                capabilities.get_rect = true;
+            }
+            if line.contains("RRGGBBAA") {
+                capabilities.alpha = true;
             }
         }
+
+        capabilities
     }
 }
@@ -1,99 +1,253 @@
 use async_trait::async_trait;
-use rand::{prelude::SliceRandom, thread_rng};
+use rand::{prelude::SliceRandom, rngs::StdRng};
 use rusttype::Font;
 use std::{
     io::Result,
-    sync::atomic::{
-        AtomicU32,
-        Ordering::{AcqRel, Acquire},
+    sync::{
+        atomic::{
+            AtomicU32,
+            Ordering::{AcqRel, Acquire},
+        },
+        Mutex,
     },
 };
 use tokio::sync::RwLock;
 
 use crate::{
-    client::{Client, AVG_BYES_PER_PIXEL_SET_COMMAND},
+    ball::Touch,
+    canvas::Canvas,
+    client,
     draw::Draw,
-    game::GoalScored,
+    events::{Events, GameEvent},
+    field_layout::ScoreArea,
+    game::{GoalScored, Team},
     image_helpers::{self, BLACK, WHITE},
-    protocol::Serialize,
+    protocol::{Encoding, PixelflutRequest},
+    viewport::Viewport,
 };
 
 pub struct Score {
     points_left: AtomicU32,
     points_right: AtomicU32,
+    /// Number of times a player of the team kicked the ball. Touches by players without a team are not counted.
+    touches_left: AtomicU32,
+    touches_right: AtomicU32,
+    own_goals_left: AtomicU32,
+    own_goals_right: AtomicU32,
+    /// Goals prepared by a teammate of the player scoring it
+    assists_left: AtomicU32,
+    assists_right: AtomicU32,
+    /// The team that touched any of the balls last
+    last_touch: Mutex<Option<Team>>,
 
     font: Font<'static>,
+    score_area_left: ScoreArea,
+    score_area_right: ScoreArea,
+    /// The score areas are relative to the viewport
+    viewport: Viewport,
+    /// Used to shuffle the draw commands, seeded by the game
+    rng: Mutex<StdRng>,
 
     draw_command_bytes: RwLock<Vec<u8>>,
+    encoding: Encoding,
+    events: Events,
 }
 
 impl Score {
-    pub async fn new() -> Self {
+    /// The points of each team are drawn into the score area of their side of the field, followed by a line with their
+    /// assists and own goals and a line showing if the team touched the ball last
+    pub async fn new(
+        encoding: Encoding,
+        viewport: Viewport,
+        score_area_left: ScoreArea,
+        score_area_right: ScoreArea,
+        rng: StdRng,
+        events: Events,
+    ) -> Self {
         let font = Font::try_from_bytes(include_bytes!("../Arial.ttf"))
             .unwrap_or_else(|| panic!("Failed to construct Font from Arial.ttf"));
         let score = Score {
             points_left: AtomicU32::new(0),
             points_right: AtomicU32::new(0),
+            touches_left: AtomicU32::new(0),
+            touches_right: AtomicU32::new(0),
+            own_goals_left: AtomicU32::new(0),
+            own_goals_right: AtomicU32::new(0),
+            assists_left: AtomicU32::new(0),
+            assists_right: AtomicU32::new(0),
+            last_touch: Mutex::new(None),
             font,
+            score_area_left,
+            score_area_right,
+            viewport,
+            rng: Mutex::new(rng),
             draw_command_bytes: RwLock::new(vec![]),
+            encoding,
+            events,
         };
         score.update_draw_commands().await;
         score
     }
 
+    pub fn points_left(&self) -> u32 {
+        self.points_left.load(Acquire)
+    }
+
+    pub fn points_right(&self) -> u32 {
+        self.points_right.load(Acquire)
+    }
+
+    pub fn touches_left(&self) -> u32 {
+        self.touches_left.load(Acquire)
+    }
+
+    pub fn touches_right(&self) -> u32 {
+        self.touches_right.load(Acquire)
+    }
+
+    /// Own goals the team on the left side shot, i.e. points it gave to the right team
+    pub fn own_goals_left(&self) -> u32 {
+        self.own_goals_left.load(Acquire)
+    }
+
+    pub fn own_goals_right(&self) -> u32 {
+        self.own_goals_right.load(Acquire)
+    }
+
+    pub fn assists_left(&self) -> u32 {
+        self.assists_left.load(Acquire)
+    }
+
+    pub fn assists_right(&self) -> u32 {
+        self.assists_right.load(Acquire)
+    }
+
+    /// The team that touched any of the balls last
+    pub fn last_touch(&self) -> Option<Team> {
+        *self.last_touch.lock().unwrap()
+    }
+
+    pub async fn count_touch(&self, touch: &Touch) {
+        let Some(team) = touch.team() else {
+            return;
+        };
+        match team {
+            Team::Left => self.touches_left.fetch_add(1, AcqRel),
+            Team::Right => self.touches_right.fetch_add(1, AcqRel),
+        };
+
+        let previous = self.last_touch.lock().unwrap().replace(team);
+        if previous != Some(team) {
+            self.update_draw_commands().await;
+        }
+    }
+
     pub async fn score_goal(&self, goal: GoalScored) {
-        match goal {
-            GoalScored::Left => {
-                self.points_right.fetch_add(1, AcqRel);
-            }
-            GoalScored::Right => {
+        match goal.scoring_team {
+            Team::Left => {
                 self.points_left.fetch_add(1, AcqRel);
             }
+            Team::Right => {
+                self.points_right.fetch_add(1, AcqRel);
+            }
         }
+        if goal.own_goal {
+            let own_goals = match goal.scoring_team.opponent() {
+                Team::Left => &self.own_goals_left,
+                Team::Right => &self.own_goals_right,
+            };
+            own_goals.fetch_add(1, AcqRel);
+        }
+        // A pass between two players of the scoring team
+        let assisting_team = goal.assist.and_then(|touch| touch.team());
+        let scoring_touch_team = goal.last_touch.and_then(|touch| touch.team());
+        if assisting_team == Some(goal.scoring_team) && scoring_touch_team == assisting_team {
+            let assists = match goal.scoring_team {
+                Team::Left => &self.assists_left,
+                Team::Right => &self.assists_right,
+            };
+            assists.fetch_add(1, AcqRel);
+        }
+        self.events.publish(GameEvent::Score {
+            points_left: self.points_left(),
+            points_right: self.points_right(),
+        });
         self.update_draw_commands().await;
     }
 
     async fn update_draw_commands(&self) {
+        let mut draw_commands = self.draw_team(Team::Left);
+        draw_commands.extend(self.draw_team(Team::Right));
+
+        // Shuffle commands to prevent drawing artefacts
+        draw_commands.shuffle(&mut *self.rng.lock().unwrap());
+
+        *(self.draw_command_bytes.write().await) =
+            client::commands_to_bytes(&draw_commands, self.encoding);
+    }
+
+    /// The points take up the score area, except for two lines at the bottom using a third of the font size
+    fn draw_team(&self, team: Team) -> Vec<PixelflutRequest> {
+        let (score_area, points, assists, own_goals) = match team {
+            Team::Left => (
+                &self.score_area_left,
+                &self.points_left,
+                &self.assists_left,
+                &self.own_goals_left,
+            ),
+            Team::Right => (
+                &self.score_area_right,
+                &self.points_right,
+                &self.assists_right,
+                &self.own_goals_right,
+            ),
+        };
+        let (x, y) = self.viewport.to_screen(score_area.x, score_area.y);
+        let line_font_size = score_area.font_size / 3.0;
+        let line_height = line_font_size.ceil() as u16;
+        let points_height = score_area.height.saturating_sub(2 * line_height);
+
+        let stats = format!(
+            "A {}  OG {}",
+            assists.load(Acquire),
+            own_goals.load(Acquire)
+        );
+        let last_touch = match self.last_touch() == Some(team) {
+            true => "last touch",
+            false => "",
+        };
         let mut draw_commands = image_helpers::draw_text_with_background(
-            20,
-            300,
-            100,
-            54,
-            60.0,
+            x,
+            y,
+            score_area.width,
+            points_height,
+            score_area.font_size,
             BLACK,
             WHITE,
-            self.points_left.load(Acquire).to_string().as_str(),
+            points.load(Acquire).to_string().as_str(),
             &self.font,
         );
-        draw_commands.extend(image_helpers::draw_text_with_background(
-            1798,
-            300,
-            100,
-            54,
-            60.0,
-            BLACK,
-            WHITE,
-            self.points_right.load(Acquire).to_string().as_str(),
-            &self.font,
-        ));
-
-        // Shuffle commands to prevent drawing artefacts
-        draw_commands.shuffle(&mut thread_rng());
-
-        let mut draw_command_bytes =
-            Vec::with_capacity(draw_commands.len() * AVG_BYES_PER_PIXEL_SET_COMMAND);
-        draw_commands.iter().for_each(|cmd| {
-            cmd.serialize(&mut draw_command_bytes);
-        });
-
-        *(self.draw_command_bytes.write().await) = draw_command_bytes;
+        for (line, text) in [stats.as_str(), last_touch].into_iter().enumerate() {
+            draw_commands.extend(image_helpers::draw_text_with_background(
+                x,
+                y + points_height + line as u16 * line_height,
+                score_area.width,
+                line_height,
+                line_font_size,
+                BLACK,
+                WHITE,
+                text,
+                &self.font,
+            ));
+        }
+        draw_commands
     }
 }
 
 #[async_trait]
 impl Draw for Score {
-    async fn draw(&self, client: &mut Client) -> Result<()> {
-        client
+    async fn draw<C: Canvas>(&self, canvas: &mut C) -> Result<()> {
+        canvas
             .write_bytes(self.draw_command_bytes.read().await.as_ref())
             .await?;
         Ok(())
@@ -0,0 +1,166 @@
+use image::{ImageFormat, Rgba, RgbaImage};
+use std::{
+    io::Result,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    time,
+};
+
+use crate::protocol::{Encoding, PixelflutRequest, PixelflutResponse, Serialize};
+
+/// Requests are read in chunks of this size
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+/// A client sending a line longer than this is not speaking Pixelflut, so the connection gets closed
+const MAX_LINE_LENGTH: usize = 1024;
+
+const HELP_TEXT: &str = "\
+Pixelflut server bundled with pixel-soccer, supported commands:
+HELP: Show this help
+SIZE: Get the size of the canvas as SIZE <width> <height>
+PX x y: Get the color of a pixel as PX <x> <y> <rrggbb>
+PX x y rrggbb: Set the color of a pixel
+PB<x:u16le><y:u16le><r:u8><g:u8><b:u8><a:u8>: Set the color of a pixel using 10 bytes
+OFFSET x y: Add an offset to the coordinates of all following commands
+";
+
+/// Minimal Pixelflut server, so that pixel-soccer can be developed and tested without a separate server.
+/// Supports `HELP`, `SIZE`, `PX x y` (read), `PX x y rrggbb(aa)` (set), `PB` (binary set) and `OFFSET x y`.
+pub struct Server {
+    canvas: Arc<Mutex<RgbaImage>>,
+}
+
+impl Server {
+    /// Creates a server with a black canvas of the given size
+    pub fn new(width: u16, height: u16) -> Self {
+        Server {
+            canvas: Arc::new(Mutex::new(RgbaImage::from_pixel(
+                width as u32,
+                height as u32,
+                Rgba([0, 0, 0, 255]),
+            ))),
+        }
+    }
+
+    /// The canvas shared by all connections, e.g. to paint players onto it or to inspect what was drawn
+    pub fn canvas(&self) -> Arc<Mutex<RgbaImage>> {
+        Arc::clone(&self.canvas)
+    }
+
+    /// Accepts connections on `listener` until accepting fails, every connection is handled in a separate task
+    pub async fn serve(&self, listener: TcpListener) -> Result<()> {
+        loop {
+            let (stream, peer) = listener.accept().await?;
+            let canvas = Arc::clone(&self.canvas);
+            tokio::spawn(async move {
+                if let Err(err) = handle_connection(stream, canvas).await {
+                    eprintln!("Connection to {peer} failed: {err}");
+                }
+            });
+        }
+    }
+
+    /// Saves the canvas as PNG to `path` every `interval`, forever
+    pub async fn dump_png(&self, path: PathBuf, interval: Duration) {
+        let mut interval = time::interval(interval);
+        interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+
+            let snapshot = self.canvas.lock().unwrap().clone();
+            let path = path.clone();
+            // Encoding a PNG takes a while, so we don't want to block the connections
+            let result = tokio::task::spawn_blocking(move || {
+                snapshot.save_with_format(&path, ImageFormat::Png)
+            })
+            .await;
+            match result {
+                Ok(Ok(())) => (),
+                Ok(Err(err)) => eprintln!("Failed to save the canvas as PNG: {err}"),
+                Err(err) => eprintln!("Saving the canvas as PNG panicked: {err}"),
+            }
+        }
+    }
+}
+
+async fn handle_connection(mut stream: TcpStream, canvas: Arc<Mutex<RgbaImage>>) -> Result<()> {
+    let mut buffer = Vec::with_capacity(READ_BUFFER_SIZE);
+    let mut responses = Vec::new();
+    let mut offset = (0_u16, 0_u16);
+
+    loop {
+        if stream.read_buf(&mut buffer).await? == 0 {
+            return Ok(());
+        }
+
+        let mut position = 0;
+        {
+            let mut canvas = canvas.lock().unwrap();
+            while let Some((request, length)) = PixelflutRequest::parse(&buffer[position..]) {
+                position += length;
+                // Same as other servers, we simply ignore requests we don't understand
+                if let Ok(request) = request {
+                    execute(request, &mut canvas, &mut offset, &mut responses);
+                }
+            }
+        }
+        buffer.drain(..position);
+
+        if buffer.len() > MAX_LINE_LENGTH {
+            eprintln!("Closing connection, as the client sent a line that is too long");
+            return Ok(());
+        }
+
+        if !responses.is_empty() {
+            stream.write_all(&responses).await?;
+            responses.clear();
+        }
+
+        // Clients drawing in a loop always have data ready, so without yielding they would starve the clients waiting
+        // for their pixel reads
+        tokio::task::yield_now().await;
+    }
+}
+
+/// Executes a single request, appending the response (if any) to `responses`
+fn execute(
+    request: PixelflutRequest,
+    canvas: &mut RgbaImage,
+    offset: &mut (u16, u16),
+    responses: &mut Vec<u8>,
+) {
+    match request {
+        PixelflutRequest::Help => responses.extend_from_slice(HELP_TEXT.as_bytes()),
+        PixelflutRequest::GetSize => PixelflutResponse::Size {
+            width: canvas.width() as u16,
+            height: canvas.height() as u16,
+        }
+        .serialize(responses, Encoding::Ascii),
+        PixelflutRequest::SetPixel { x, y, rgb } => {
+            let (x, y) = (x.saturating_add(offset.0), y.saturating_add(offset.1));
+            if let Some(pixel) = canvas.get_pixel_mut_checked(x as u32, y as u32) {
+                let [_, r, g, b] = rgb.to_be_bytes();
+                *pixel = Rgba([r, g, b, 255]);
+            }
+        }
+        PixelflutRequest::GetPixel { x, y } => {
+            let (x, y) = (x.saturating_add(offset.0), y.saturating_add(offset.1));
+            // Reads outside of the canvas are ignored
+            if let Some(pixel) = canvas.get_pixel_checked(x as u32, y as u32) {
+                let [r, g, b, _] = pixel.0;
+                PixelflutResponse::Pixel {
+                    x,
+                    y,
+                    rgb: u32::from_be_bytes([0, r, g, b]),
+                }
+                .serialize(responses, Encoding::Ascii);
+            }
+        }
+        PixelflutRequest::SetOffset { x, y } => *offset = (x, y),
+    }
+}
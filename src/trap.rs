@@ -0,0 +1,120 @@
+use std::{f32::consts::PI, fmt::Display, str::FromStr};
+
+/// Number of directions that are tried when looking for a free spot
+const ESCAPE_DIRECTIONS: usize = 16;
+/// Number of distances (in multiples of half the ball radius) that are tried when looking for a free spot
+const ESCAPE_DISTANCES: usize = 8;
+/// Number of points on each circle that are checked to decide if a spot is free
+const FREE_SPOT_SAMPLES: usize = 12;
+
+/// How to detect a ball that got trapped by players painting over it, and how to free it again
+#[derive(Clone, Copy, Debug)]
+pub struct TrapConfig {
+    pub policy: TrapPolicy,
+    /// Number of consecutive ticks the ball needs to be surrounded by player pixels to be considered trapped
+    pub ticks: u32,
+    /// Fraction of the pixels in the ring around the ball that need to be player pixels in a tick
+    pub ratio: f32,
+}
+
+/// What to do with a trapped ball
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrapPolicy {
+    /// Move the ball to the nearest spot not covered by any player pixels
+    Teleport,
+    /// Let the ball pass through player pixels for the given number of ticks
+    PassThrough(u32),
+    /// Put the ball back to kick-off
+    Reset,
+}
+
+impl FromStr for TrapPolicy {
+    type Err = String;
+
+    /// Parses `teleport`, `pass-through:<ticks>` or `reset`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "teleport" => Ok(TrapPolicy::Teleport),
+            None if s == "reset" => Ok(TrapPolicy::Reset),
+            Some(("pass-through", ticks)) => ticks
+                .parse::<u32>()
+                .ok()
+                .filter(|ticks| *ticks > 0)
+                .map(TrapPolicy::PassThrough)
+                .ok_or_else(|| format!("Invalid ticks {ticks:?}, must be a positive number")),
+            _ => Err(format!(
+                "Invalid trap policy {s:?}, expected one of \"teleport\", \"pass-through:<ticks>\" or \"reset\""
+            )),
+        }
+    }
+}
+
+impl Display for TrapPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TrapPolicy::Teleport => write!(f, "teleport"),
+            TrapPolicy::PassThrough(ticks) => write!(f, "pass-through:{ticks}"),
+            TrapPolicy::Reset => write!(f, "reset"),
+        }
+    }
+}
+
+/// Returns the centers the ball could be teleported to, grouped by their distance to the current center (nearest
+/// first). Only centers keeping the whole ball on the screen are returned.
+pub fn escape_candidates(
+    x_center: f32,
+    y_center: f32,
+    radius: f32,
+    screen_width: u16,
+    screen_height: u16,
+) -> Vec<Vec<(f32, f32)>> {
+    (1..=ESCAPE_DISTANCES)
+        .map(|distance| {
+            let distance = distance as f32 * radius / 2.0;
+            (0..ESCAPE_DIRECTIONS)
+                .map(|direction| {
+                    let direction = direction as f32 * 2.0 * PI / ESCAPE_DIRECTIONS as f32;
+                    (
+                        x_center + distance * direction.cos(),
+                        y_center + distance * direction.sin(),
+                    )
+                })
+                .filter(|(x, y)| {
+                    *x - radius > 0.0
+                        && *x + radius < screen_width as f32
+                        && *y - radius > 0.0
+                        && *y + radius < screen_height as f32
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Returns a sparse set of pixels covering the area of a ball at the given center, which is good enough to decide if
+/// the spot is free. All coordinates are within `radius` around the center.
+pub fn free_spot_coordinates(
+    x_center: i16,
+    y_center: i16,
+    radius: f32,
+    screen_width: u16,
+    screen_height: u16,
+) -> Vec<(u16, u16)> {
+    let mut coordinates = vec![(x_center, y_center)];
+    for circle_radius in [radius / 2.0, radius] {
+        for sample in 0..FREE_SPOT_SAMPLES {
+            let angle = sample as f32 * 2.0 * PI / FREE_SPOT_SAMPLES as f32;
+            coordinates.push((
+                x_center + (circle_radius * angle.cos()) as i16,
+                y_center + (circle_radius * angle.sin()) as i16,
+            ));
+        }
+    }
+
+    coordinates
+        .into_iter()
+        .filter(|(x, y)| {
+            *x >= 0 && *x < screen_width as i16 && *y >= 0 && *y < screen_height as i16
+        })
+        .map(|(x, y)| (x as u16, y as u16))
+        .collect()
+}
@@ -0,0 +1,63 @@
+use std::{fmt::Display, str::FromStr};
+
+/// Region of the screen the game is confined to, e.g. when the Pixelflut wall is shared with other projects.
+/// The game only sees the viewport: Coordinates within the game are relative to its top left corner and the size of
+/// the viewport is used as the screen size.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Viewport {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+impl Viewport {
+    pub fn fits_into(&self, screen_width: u16, screen_height: u16) -> bool {
+        self.x as u32 + self.width as u32 <= screen_width as u32
+            && self.y as u32 + self.height as u32 <= screen_height as u32
+    }
+
+    /// Translates coordinates within the viewport to coordinates on the screen
+    pub fn to_screen(&self, x: u16, y: u16) -> (u16, u16) {
+        (self.x + x, self.y + y)
+    }
+
+    /// Translates coordinates on the screen to coordinates within the viewport, if they are part of it
+    pub fn to_viewport(&self, x: u16, y: u16) -> Option<(u16, u16)> {
+        let (x, y) = (x.checked_sub(self.x)?, y.checked_sub(self.y)?);
+        (x < self.width && y < self.height).then_some((x, y))
+    }
+}
+
+impl FromStr for Viewport {
+    type Err = String;
+
+    /// Parses `<x>,<y>,<width>,<height>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values = s
+            .split(',')
+            .map(|value| value.trim().parse::<u16>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| format!("Invalid viewport {s:?}: {err}"))?;
+        match values[..] {
+            [x, y, width, height] if width > 0 && height > 0 => Ok(Viewport {
+                x,
+                y,
+                width,
+                height,
+            }),
+            [_, _, _, _] => Err(format!(
+                "Invalid viewport {s:?}, the width and height must be positive"
+            )),
+            _ => Err(format!(
+                "Invalid viewport {s:?}, expected \"<x>,<y>,<width>,<height>\""
+            )),
+        }
+    }
+}
+
+impl Display for Viewport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{},{},{},{}", self.x, self.y, self.width, self.height)
+    }
+}
@@ -0,0 +1,153 @@
+// Every test binary compiles this module on its own, but not all of them use every helper
+#![allow(dead_code)]
+
+use image::{Rgba, RgbaImage};
+use pixel_soccer::{
+    ball::{BallConfig, BallPhysics},
+    canvas::Canvas,
+    events::Events,
+    field_layout::FieldLayout,
+    game::{Game, Goal, GoalScored},
+    player_colors::{ColorTolerance, PlayerColors},
+    probe::{ProbeConfig, ProbeSampling},
+    trap::{TrapConfig, TrapPolicy},
+};
+use std::{
+    f32::consts::PI,
+    ops::Range,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+pub const SEED: u64 = 42;
+pub const SPEED: f32 = 10.0;
+pub const MAX_SPEED: f32 = 20.0;
+pub const HIT_ACCELERATION: f32 = 1.5;
+
+/// Color the players paint with
+pub const RED: Rgba<u8> = Rgba([255, 0, 0, 255]);
+
+/// Only exactly red players, the same as the defaults of the command line
+pub fn player_colors() -> PlayerColors {
+    PlayerColors {
+        shared: vec![0xff0000],
+        left: vec![],
+        right: vec![],
+        tolerance: ColorTolerance::Exact,
+    }
+}
+
+pub fn ball_config() -> BallConfig {
+    ball_config_with_colors(player_colors())
+}
+
+pub fn ball_config_with_colors(colors: PlayerColors) -> BallConfig {
+    ball_config_with(colors, full_probe(), trap())
+}
+
+/// Only probes a quarter of the ring in front of the ball and considers the ball trapped after a single tick
+pub fn ball_config_with_narrow_probe() -> BallConfig {
+    let probe = ProbeConfig {
+        sector_angle: PI / 2.0,
+        sampling: ProbeSampling::Full,
+    };
+    let trap = TrapConfig {
+        policy: TrapPolicy::Teleport,
+        ticks: 1,
+        ratio: 0.6,
+    };
+    ball_config_with(player_colors(), probe, trap)
+}
+
+/// Always moves `speed` pixels per tick, split into sub-steps of at most `max_step` pixels
+pub fn ball_config_with_speed(speed: f32, max_step: f32) -> BallConfig {
+    let physics = BallPhysics {
+        speed,
+        min_speed: speed,
+        max_speed: speed,
+        max_step,
+        ..physics()
+    };
+    ball_config_with_physics(physics, player_colors(), full_probe(), trap())
+}
+
+/// Checks the whole ring around the ball, so that the tests don't depend on the probed sector
+fn full_probe() -> ProbeConfig {
+    ProbeConfig {
+        sector_angle: 2.0 * PI,
+        sampling: ProbeSampling::Full,
+    }
+}
+
+fn trap() -> TrapConfig {
+    TrapConfig {
+        policy: TrapPolicy::Teleport,
+        ticks: 5,
+        ratio: 0.6,
+    }
+}
+
+fn physics() -> BallPhysics {
+    BallPhysics {
+        speed: SPEED,
+        min_speed: SPEED,
+        max_speed: MAX_SPEED,
+        friction: 0.0,
+        hit_acceleration: HIT_ACCELERATION,
+        max_step: SPEED,
+    }
+}
+
+fn ball_config_with(colors: PlayerColors, probe: ProbeConfig, trap: TrapConfig) -> BallConfig {
+    ball_config_with_physics(physics(), colors, probe, trap)
+}
+
+fn ball_config_with_physics(
+    physics: BallPhysics,
+    colors: PlayerColors,
+    probe: ProbeConfig,
+    trap: TrapConfig,
+) -> BallConfig {
+    BallConfig::new("images/ball_v1.png", None, physics, probe, trap, colors)
+        .expect("The ball config used in the tests must be valid")
+}
+
+pub fn field_layout() -> FieldLayout {
+    FieldLayout::load(Path::new("fields/default.toml")).expect("Failed to load the default field")
+}
+
+pub async fn new_game<C: Canvas + 'static>(canvas: C, number_of_balls: u16) -> Game<C> {
+    Game::new(
+        canvas,
+        None,
+        field_layout(),
+        ball_config(),
+        number_of_balls,
+        SEED,
+        Events::new(),
+    )
+    .await
+    .expect("Failed to start the game")
+}
+
+/// Starts a game with a single ball using the given config
+pub async fn new_game_with_config<C: Canvas + 'static>(canvas: C, config: BallConfig) -> Game<C> {
+    Game::new(canvas, None, field_layout(), config, 1, SEED, Events::new())
+        .await
+        .expect("Failed to start the game")
+}
+
+/// Paints a rectangle onto the canvas, the same way players would do it
+pub fn paint_rect(image: &Arc<Mutex<RgbaImage>>, x: Range<u32>, y: Range<u32>, color: Rgba<u8>) {
+    let mut image = image.lock().unwrap();
+    for x in x {
+        for y in y.clone() {
+            image.put_pixel(x, y, color);
+        }
+    }
+}
+
+/// The goals the balls went into, without who scored them
+pub fn goals(scored: Vec<GoalScored>) -> Vec<Goal> {
+    scored.into_iter().map(|goal| goal.goal).collect()
+}
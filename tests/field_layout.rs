@@ -0,0 +1,182 @@
+//! Loads alternative arenas from field definition files and fits them to the screen
+
+mod common;
+
+use common::{ball_config, field_layout, goals, new_game, SEED, SPEED};
+use pixel_soccer::{
+    events::Events,
+    field_layout::FieldLayout,
+    game::{Game, Goal},
+    memory_canvas::MemoryCanvas,
+};
+use std::{fs, io::ErrorKind, path::PathBuf};
+
+/// Writes a field definition using the default images, with the given goals and kick-off point
+fn write_definition(name: &str, left_goal: &str, right_goal: &str, kick_off: &str) -> PathBuf {
+    let images = fs::canonicalize("images").unwrap();
+    let definition = format!(
+        r#"
+image = "{images}/field_v3.png"
+hitbox = "{images}/field_v3_hitbox.png"
+kick_off = {kick_off}
+
+[left]
+goal = {left_goal}
+score = {{ x = 20, y = 20, width = 100, height = 54, font_size = 60.0 }}
+
+[right]
+goal = {right_goal}
+score = {{ x = 1798, y = 20, width = 100, height = 54, font_size = 60.0 }}
+"#,
+        images = images.display()
+    );
+
+    let path =
+        std::env::temp_dir().join(format!("pixel-soccer-{}-{name}.toml", std::process::id()));
+    fs::write(&path, definition).unwrap();
+    path
+}
+
+#[tokio::test]
+async fn goals_and_kick_off_come_from_the_definition() {
+    // Goals on the top and bottom edge instead of the left and right one
+    let path = write_definition(
+        "vertical",
+        "{ x = 860, y = 0, width = 200, height = 10 }",
+        "{ x = 860, y = 1070, width = 200, height = 10 }",
+        "{ x = 500, y = 500 }",
+    );
+    let field_layout = FieldLayout::load(&path).unwrap();
+    fs::remove_file(path).unwrap();
+
+    let mut game = Game::new(
+        MemoryCanvas::new(1920, 1080),
+        None,
+        field_layout,
+        ball_config(),
+        1,
+        SEED,
+        Events::new(),
+    )
+    .await
+    .unwrap();
+    let ball = &game.balls()[0];
+    assert_eq!(ball.center(), (500.0, 500.0));
+
+    // The left and right edge are no goals any more
+    ball.set_center(45.0, 540.0);
+    ball.set_velocity(-SPEED, 0.0);
+    assert!(game.tick().await.is_empty());
+
+    let ball = &game.balls()[0];
+    ball.set_center(960.0, 1025.0);
+    ball.set_velocity(0.0, SPEED);
+    assert_eq!(goals(game.tick().await), vec![Goal::Right]);
+    assert_eq!(game.score().points_left(), 1);
+    assert_eq!(game.balls()[0].center(), (500.0, 500.0));
+}
+
+#[test]
+fn invalid_definitions_are_rejected() {
+    let path = write_definition(
+        "kick-off-outside",
+        "{ x = 0, y = 367, width = 10, height = 346 }",
+        "{ x = 1910, y = 367, width = 10, height = 346 }",
+        "{ x = 2000, y = 500 }",
+    );
+    let err = FieldLayout::load(&path).err().unwrap();
+    fs::remove_file(path).unwrap();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+    let path = write_definition(
+        "goal-outside",
+        "{ x = 0, y = 367, width = 10, height = 346 }",
+        "{ x = 1915, y = 367, width = 10, height = 346 }",
+        "{ x = 960, y = 540 }",
+    );
+    let err = FieldLayout::load(&path).err().unwrap();
+    fs::remove_file(path).unwrap();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+}
+
+#[tokio::test]
+async fn field_is_scaled_to_smaller_screen() {
+    let mut game = new_game(MemoryCanvas::new(1280, 720), 1).await;
+    let ball = &game.balls()[0];
+    assert_eq!(ball.center(), (640.0, 360.0));
+    // The ball shrinks and slows down together with the field
+    let (velocity_x, velocity_y) = ball.velocity();
+    let scaled_speed = SPEED * 720.0 / 1080.0;
+    assert!((velocity_x.hypot(velocity_y) - scaled_speed).abs() < 0.01);
+
+    // The left goal shrank from 10 to 7 pixels, but is still at the screen edge
+    ball.set_center(50.0, 360.0);
+    ball.set_velocity(-scaled_speed, 0.0);
+    let mut scored = Vec::new();
+    for _ in 0..5 {
+        scored.extend(goals(game.tick().await));
+    }
+    assert_eq!(scored, vec![Goal::Left]);
+    assert_eq!(game.score().points_right(), 1);
+}
+
+#[tokio::test]
+async fn field_is_letterboxed_on_taller_screen() {
+    let field_layout = field_layout().fit_to_screen(1920, 1200);
+    let transform = field_layout.transform;
+    assert_eq!(transform.scale, 1.0);
+    assert_eq!(transform.to_screen(0.0, 0.0), (0.0, 60.0));
+    assert_eq!(field_layout.left.goal.y, 367 + 60);
+
+    let mut game = Game::new(
+        MemoryCanvas::new(1920, 1200),
+        None,
+        field_layout,
+        ball_config(),
+        1,
+        SEED,
+        Events::new(),
+    )
+    .await
+    .unwrap();
+    let ball = &game.balls()[0];
+    assert_eq!(ball.center(), (960.0, 600.0));
+
+    // The bar above the field is a wall, although the screen continues
+    ball.set_center(960.0, 120.0);
+    ball.set_velocity(0.0, -SPEED);
+    for _ in 0..5 {
+        game.tick().await;
+    }
+    let ball = &game.balls()[0];
+    assert!(
+        ball.velocity().1 > 0.0,
+        "ball should move down after the bounce"
+    );
+    assert!(ball.center().1 > 60.0, "ball must not leave the field");
+}
+
+#[tokio::test]
+async fn balls_kick_off_on_the_letterboxed_field() {
+    // The field only covers y = 460..1540, the bars above and below are walls
+    let mut game = new_game(MemoryCanvas::new(1920, 2000), 20).await;
+    let radius = ball_config().radius();
+    // Bouncing balls can reach into the walls by up to half a step, see `BallConfig::new`
+    let on_field = |game: &Game<MemoryCanvas>, margin: f32| {
+        game.balls().iter().all(|ball| {
+            let (x, y) = ball.center();
+            (radius - margin..=1920.0 - radius + margin).contains(&x)
+                && (460.0 + radius - margin..=1540.0 - radius + margin).contains(&y)
+        })
+    };
+    assert!(on_field(&game, 0.0), "all balls must kick off on the field");
+
+    for _ in 0..100 {
+        game.tick().await;
+    }
+    let centers: Vec<_> = game.balls().iter().map(|ball| ball.center()).collect();
+    assert!(
+        on_field(&game, SPEED / 2.0),
+        "all balls must stay on the field: {centers:?}"
+    );
+}
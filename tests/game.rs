@@ -0,0 +1,697 @@
+//! Plays games on an in-memory canvas, so the game logic can be tested tick by tick without a Pixelflut server
+
+mod common;
+
+use common::{
+    ball_config, ball_config_with_colors, ball_config_with_narrow_probe, ball_config_with_speed,
+    field_layout, goals, new_game, new_game_with_config, paint_rect, player_colors,
+    HIT_ACCELERATION, MAX_SPEED, RED, SEED, SPEED,
+};
+use image::Rgba;
+use pixel_soccer::{
+    ball::Touch,
+    canvas::{Canvas, FIELD_HITBOX_COLOR},
+    events::{Events, GameEvent},
+    game::{Game, Goal, GoalScored, Team},
+    memory_canvas::MemoryCanvas,
+    player_colors::{parse_color, ColorTolerance, Player, PlayerColors},
+};
+
+const WIDTH: u16 = 1920;
+const HEIGHT: u16 = 1080;
+
+#[tokio::test]
+async fn ball_bounces_off_player() {
+    let canvas = MemoryCanvas::new(WIDTH, HEIGHT);
+    let image = canvas.image();
+    let mut game = new_game(canvas, 1).await;
+    let ball = &game.balls()[0];
+
+    // A player standing in the way of the ball
+    paint_rect(&image, 660..670, 400..680, RED);
+    ball.set_center(600.0, 540.0);
+    ball.set_velocity(SPEED, 0.0);
+
+    for _ in 0..5 {
+        game.tick().await;
+    }
+
+    let ball = &game.balls()[0];
+    let (velocity_x, velocity_y) = ball.velocity();
+    assert!(velocity_x < 0.0, "ball should move left after the bounce");
+    assert!(velocity_y.abs() < 1.0, "the player was hit head-on");
+    let speed = velocity_x.hypot(velocity_y);
+    assert!(
+        (speed - SPEED * HIT_ACCELERATION).abs() < 0.01,
+        "hitting a player accelerates the ball, but the speed is {speed}"
+    );
+    assert!(
+        ball.center().0 < 660.0 - 40.0,
+        "ball must not enter the player"
+    );
+}
+
+#[tokio::test]
+async fn fast_ball_does_not_tunnel_through_thin_player() {
+    let canvas = MemoryCanvas::new(WIDTH, HEIGHT);
+    let image = canvas.image();
+    // Four sub-steps per tick, so a single step would jump over the line
+    let mut game = new_game_with_config(canvas, ball_config_with_speed(40.0, 10.0)).await;
+    let ball = &game.balls()[0];
+
+    paint_rect(&image, 1000..1001, 300..780, RED);
+    ball.set_center(800.0, 540.0);
+    ball.set_velocity(40.0, 0.0);
+
+    for _ in 0..10 {
+        game.tick().await;
+        let (x, _) = game.balls()[0].center();
+        assert!(x < 1000.0, "ball passed through the player to x {x}");
+    }
+    assert!(
+        game.balls()[0].velocity().0 < 0.0,
+        "ball should move left after the bounce"
+    );
+}
+
+#[tokio::test]
+async fn ball_bounces_off_screen_edge() {
+    let mut game = new_game(MemoryCanvas::new(WIDTH, HEIGHT), 1).await;
+    let ball = &game.balls()[0];
+
+    ball.set_center(960.0, 60.0);
+    ball.set_velocity(0.0, -SPEED);
+
+    for _ in 0..5 {
+        game.tick().await;
+    }
+
+    let ball = &game.balls()[0];
+    let (_, velocity_y) = ball.velocity();
+    assert!(velocity_y > 0.0, "ball should move down after the bounce");
+    let speed = ball.velocity().0.hypot(velocity_y);
+    assert!(
+        (speed - SPEED).abs() < 0.01,
+        "the screen edge is not a player, so the ball must not get faster"
+    );
+}
+
+/// The goal posts are only part of the field hitbox, not of the canvas. The ball must bounce off them anyways.
+#[tokio::test]
+async fn ball_bounces_off_field_hitbox() {
+    let mut game = new_game(MemoryCanvas::new(WIDTH, HEIGHT), 1).await;
+    let ball = &game.balls()[0];
+
+    // Right above the lower post of the left goal, which starts at y = 713
+    ball.set_center(100.0, 660.0);
+    ball.set_velocity(0.0, SPEED);
+
+    for _ in 0..5 {
+        game.tick().await;
+    }
+
+    let ball = &game.balls()[0];
+    assert!(
+        ball.velocity().1 < 0.0,
+        "ball should move up after the bounce"
+    );
+    assert!(
+        ball.center().1 + 40.0 <= 713.0,
+        "ball must not enter the post"
+    );
+    assert_eq!(game.score().points_left(), 0);
+    assert_eq!(game.score().points_right(), 0);
+}
+
+#[tokio::test]
+async fn goal_in_left_goal_scores_for_right_team() {
+    let mut game = new_game(MemoryCanvas::new(WIDTH, HEIGHT), 1).await;
+    let ball = &game.balls()[0];
+    let kick_off = ball.center();
+
+    ball.set_center(45.0, 540.0);
+    ball.set_velocity(-SPEED, 0.0);
+
+    assert_eq!(goals(game.tick().await), vec![Goal::Left]);
+    assert_eq!(game.score().points_left(), 0);
+    assert_eq!(game.score().points_right(), 1);
+    assert_eq!(
+        game.balls()[0].center(),
+        kick_off,
+        "ball must be back at kick-off"
+    );
+}
+
+#[tokio::test]
+async fn goal_in_right_goal_scores_for_left_team() {
+    let mut game = new_game(MemoryCanvas::new(WIDTH, HEIGHT), 1).await;
+    let ball = &game.balls()[0];
+    let kick_off = ball.center();
+
+    ball.set_center(1875.0, 540.0);
+    ball.set_velocity(SPEED, 0.0);
+
+    assert_eq!(goals(game.tick().await), vec![Goal::Right]);
+    assert_eq!(game.score().points_left(), 1);
+    assert_eq!(game.score().points_right(), 0);
+    assert_eq!(
+        game.balls()[0].center(),
+        kick_off,
+        "ball must be back at kick-off"
+    );
+}
+
+#[tokio::test]
+async fn only_the_ball_in_the_goal_is_reset() {
+    let mut game = new_game(MemoryCanvas::new(WIDTH, HEIGHT), 2).await;
+    let (scoring, playing) = (&game.balls()[0], &game.balls()[1]);
+
+    scoring.set_center(1875.0, 540.0);
+    scoring.set_velocity(SPEED, 0.0);
+    playing.set_center(960.0, 200.0);
+    playing.set_velocity(SPEED, 0.0);
+
+    assert_eq!(goals(game.tick().await), vec![Goal::Right]);
+    assert_eq!(game.score().points_left(), 1);
+    let (x, y) = game.balls()[1].center();
+    assert!((x - (960.0 + SPEED)).abs() < 0.01 && y == 200.0);
+}
+
+#[tokio::test]
+async fn balls_do_not_start_on_top_of_each_other() {
+    let game = new_game(MemoryCanvas::new(WIDTH, HEIGHT), 20).await;
+    let radius = ball_config().radius();
+
+    let centers: Vec<_> = game.balls().iter().map(|ball| ball.center()).collect();
+    for (i, (x, y)) in centers.iter().enumerate() {
+        assert!(
+            (radius..=WIDTH as f32 - radius).contains(x)
+                && (radius..=HEIGHT as f32 - radius).contains(y),
+            "ball {i} at ({x}, {y}) must be on the screen"
+        );
+        for (other_x, other_y) in &centers[i + 1..] {
+            assert!((other_x - x).hypot(other_y - y) >= 2.0 * radius);
+        }
+    }
+}
+
+#[tokio::test]
+async fn colliding_balls_exchange_velocities() {
+    let mut game = new_game(MemoryCanvas::new(WIDTH, HEIGHT), 2).await;
+    let radius = ball_config().radius();
+    let (ball, other) = (&game.balls()[0], &game.balls()[1]);
+
+    // After moving, the balls overlap along the x axis
+    ball.set_center(900.0, 540.0);
+    ball.set_velocity(SPEED, 0.0);
+    other.set_center(900.0 + SPEED + 1.5 * radius, 540.0 - SPEED);
+    other.set_velocity(0.0, SPEED);
+
+    game.tick().await;
+
+    let (ball, other) = (&game.balls()[0], &game.balls()[1]);
+    let (velocity_x, velocity_y) = ball.velocity();
+    assert!(
+        (velocity_x - SPEED).abs() < 0.01 && velocity_y.abs() < 0.01,
+        "the ball passes all of its speed on, but keeps moving into its direction with the minimum speed"
+    );
+    let (other_velocity_x, other_velocity_y) = other.velocity();
+    assert!((other_velocity_x - SPEED).abs() < 0.01 && (other_velocity_y - SPEED).abs() < 0.01);
+    assert!(other.center().0 - ball.center().0 >= 2.0 * radius - 0.01);
+}
+
+#[tokio::test]
+async fn colliding_balls_are_not_pushed_off_the_screen() {
+    let mut game = new_game(MemoryCanvas::new(WIDTH, HEIGHT), 2).await;
+    let radius = ball_config().radius();
+    let (ball, other) = (&game.balls()[0], &game.balls()[1]);
+
+    // Overlapping at the top edge, the upper ball can't make room
+    ball.set_center(960.0, radius + 1.0);
+    ball.set_velocity(SPEED, 0.0);
+    other.set_center(960.0, 2.0 * radius + 1.0);
+    other.set_velocity(SPEED, 0.0);
+
+    game.tick().await;
+
+    let (_, y) = game.balls()[0].center();
+    assert!(y >= radius, "ball was pushed off the screen to y {y}");
+}
+
+#[tokio::test]
+async fn colliding_balls_keep_their_speed_limits() {
+    let mut game = new_game(MemoryCanvas::new(WIDTH, HEIGHT), 30).await;
+
+    // The balls start close to each other, so they collide a lot
+    for tick in 0..100 {
+        game.tick().await;
+        for (i, ball) in game.balls().iter().enumerate() {
+            let (velocity_x, velocity_y) = ball.velocity();
+            let speed = velocity_x.hypot(velocity_y);
+            assert!(
+                (SPEED - 0.01..=MAX_SPEED + 0.01).contains(&speed),
+                "ball {i} has speed {speed} after tick {tick}"
+            );
+        }
+    }
+}
+
+#[tokio::test]
+async fn no_goal_in_open_field() {
+    let mut game = new_game(MemoryCanvas::new(WIDTH, HEIGHT), 1).await;
+    let ball = &game.balls()[0];
+
+    ball.set_center(300.0, 540.0);
+    ball.set_velocity(-SPEED, 0.0);
+
+    // Up to the goal mouth, but not into the goal
+    for _ in 0..20 {
+        assert!(game.tick().await.is_empty());
+    }
+    assert_eq!(game.score().points_left(), 0);
+    assert_eq!(game.score().points_right(), 0);
+}
+
+#[tokio::test]
+async fn screen_donut_merges_field_hitbox() {
+    let mut canvas = MemoryCanvas::new(WIDTH, HEIGHT);
+    let image = canvas.image();
+    let hitbox = image::open("images/field_v3_hitbox.png").unwrap();
+
+    // Above the lower post of the left goal
+    paint_rect(&image, 70..71, 690..691, image::Rgba([0, 255, 0, 255]));
+    // On the post, the hitbox wins
+    paint_rect(&image, 70..71, 715..716, RED);
+
+    let (x_center, y_center, radius) = (70, 700, 30.0);
+    let donut = canvas
+        .get_screen_donut(
+            x_center,
+            y_center,
+            0.0,
+            radius,
+            WIDTH,
+            HEIGHT,
+            Some(&hitbox),
+        )
+        .await
+        .unwrap();
+    let cell = |donut: &[Vec<u32>], x: i16, y: i16| {
+        donut[(x - x_center + radius as i16) as usize][(y - y_center + radius as i16) as usize]
+    };
+
+    assert_eq!(cell(&donut, 70, 690), 0x00ff00);
+    assert_eq!(cell(&donut, 70, 715), FIELD_HITBOX_COLOR);
+    assert_eq!(cell(&donut, 90, 720), FIELD_HITBOX_COLOR);
+    assert_eq!(cell(&donut, 90, 705), 0x000000);
+
+    // Without the hitbox we only see what is on the canvas
+    let donut = canvas
+        .get_screen_donut(x_center, y_center, 0.0, radius, WIDTH, HEIGHT, None)
+        .await
+        .unwrap();
+    assert_eq!(cell(&donut, 70, 715), 0xff0000);
+    assert_eq!(cell(&donut, 90, 720), 0x000000);
+}
+
+#[tokio::test]
+async fn same_seed_gives_same_kick_off() {
+    let mut velocities = Vec::new();
+    for seed in [1, 1, 2] {
+        let game = Game::new(
+            MemoryCanvas::new(WIDTH, HEIGHT),
+            None,
+            field_layout(),
+            ball_config(),
+            3,
+            seed,
+            Events::new(),
+        )
+        .await
+        .unwrap();
+        velocities.push(
+            game.balls()
+                .iter()
+                .map(|ball| ball.velocity())
+                .collect::<Vec<_>>(),
+        );
+    }
+
+    assert_eq!(velocities[0], velocities[1]);
+    assert_ne!(velocities[0], velocities[2]);
+}
+
+/// Returns if the ball got trapped within the given number of ticks
+async fn is_trapped<C: Canvas + 'static>(game: &mut Game<C>, ticks: usize) -> bool {
+    let mut events = game.events().subscribe();
+    for _ in 0..ticks {
+        game.tick().await;
+    }
+    std::iter::from_fn(|| events.try_recv().ok())
+        .any(|event| matches!(event, GameEvent::Trapped { .. }))
+}
+
+#[tokio::test]
+async fn ball_cupped_by_a_player_is_not_trapped() {
+    let canvas = MemoryCanvas::new(WIDTH, HEIGHT);
+    let image = canvas.image();
+    let mut game = new_game_with_config(canvas, ball_config_with_narrow_probe()).await;
+    let ball = &game.balls()[0];
+
+    // A player cupping the front of the ball. The probe in front of the ball only sees the player, but the ball can
+    // bounce back.
+    let radius = ball_config().radius();
+    {
+        let mut image = image.lock().unwrap();
+        for x in 800..1000 {
+            for y in 300..780 {
+                if (x as f32 - 800.0).hypot(y as f32 - 540.0) >= radius - 1.0 {
+                    image.put_pixel(x, y, RED);
+                }
+            }
+        }
+    }
+    ball.set_center(800.0, 540.0);
+    ball.set_velocity(SPEED, 0.0);
+
+    assert!(!is_trapped(&mut game, 5).await);
+}
+
+#[tokio::test]
+async fn surrounded_ball_is_trapped() {
+    let canvas = MemoryCanvas::new(WIDTH, HEIGHT);
+    let image = canvas.image();
+    let mut game = new_game_with_config(canvas, ball_config_with_narrow_probe()).await;
+    let ball = &game.balls()[0];
+
+    paint_rect(&image, 600..1000, 300..780, RED);
+    ball.set_center(800.0, 540.0);
+    ball.set_velocity(SPEED, 0.0);
+
+    assert!(is_trapped(&mut game, 1).await);
+}
+
+/// Plays the ball into a player painted with `color` and returns who kicked it
+async fn kick_ball_into(color: Rgba<u8>, colors: PlayerColors) -> Option<Player> {
+    let mut canvas = MemoryCanvas::new(WIDTH, HEIGHT);
+    let image = canvas.image();
+    let game = new_game_with_config(
+        canvas.new_connection().await.unwrap(),
+        ball_config_with_colors(colors),
+    )
+    .await;
+    let ball = &game.balls()[0];
+
+    paint_rect(&image, 660..670, 400..680, color);
+    ball.set_center(600.0, 540.0);
+    ball.set_velocity(SPEED, 0.0);
+
+    let mut kicked_by = None;
+    for _ in 0..5 {
+        kicked_by = ball.tick(&mut canvas).await.unwrap().or(kicked_by);
+    }
+    kicked_by.map(|touch| touch.player)
+}
+
+#[tokio::test]
+async fn similar_colors_need_a_tolerance() {
+    let almost_red = Rgba([254, 0, 0, 255]);
+    let anti_aliased_red = Rgba([224, 16, 16, 255]);
+
+    assert_eq!(kick_ball_into(almost_red, player_colors()).await, None);
+
+    let colors = PlayerColors {
+        tolerance: ColorTolerance::Rgb(10.0),
+        ..player_colors()
+    };
+    assert_eq!(
+        kick_ball_into(almost_red, colors.clone()).await,
+        Some(Player::Anyone)
+    );
+    assert_eq!(kick_ball_into(anti_aliased_red, colors).await, None);
+
+    let colors = PlayerColors {
+        tolerance: "hsv:10,0.2,0.2".parse().unwrap(),
+        ..player_colors()
+    };
+    assert_eq!(
+        kick_ball_into(anti_aliased_red, colors).await,
+        Some(Player::Anyone)
+    );
+}
+
+#[test]
+fn colors_are_six_hex_digits() {
+    assert_eq!(parse_color("#ff0000"), Ok(0xff0000));
+    assert_eq!(parse_color("00ff00"), Ok(0x00ff00));
+    assert!(parse_color("+fffff").is_err());
+    assert!(parse_color("ff00").is_err());
+}
+
+#[test]
+fn field_colors_can_match_player_colors() {
+    let field = image::open("images/field_v3.png").unwrap();
+    assert!(player_colors().matching_colors(&field).is_empty());
+
+    // Everything counts as a player
+    let colors = PlayerColors {
+        tolerance: ColorTolerance::Rgb(500.0),
+        ..player_colors()
+    };
+    assert!(!colors.matching_colors(&field).is_empty());
+}
+
+#[tokio::test]
+async fn touches_are_attributed_to_teams() {
+    let colors = PlayerColors {
+        shared: vec![0x00ff00],
+        left: vec![0xff0000],
+        right: vec![0x0000ff],
+        tolerance: ColorTolerance::Exact,
+    };
+
+    assert_eq!(
+        kick_ball_into(RED, colors.clone()).await,
+        Some(Player::Team(Team::Left))
+    );
+    assert_eq!(
+        kick_ball_into(Rgba([0, 0, 255, 255]), colors.clone()).await,
+        Some(Player::Team(Team::Right))
+    );
+    assert_eq!(
+        kick_ball_into(Rgba([0, 255, 0, 255]), colors.clone()).await,
+        Some(Player::Anyone)
+    );
+    assert_eq!(kick_ball_into(Rgba([255, 255, 0, 255]), colors).await, None);
+}
+
+#[tokio::test]
+async fn rattling_between_lines_of_a_team_is_one_touch() {
+    let colors = PlayerColors {
+        shared: vec![],
+        left: vec![0xff0000],
+        right: vec![],
+        tolerance: ColorTolerance::Exact,
+    };
+    let canvas = MemoryCanvas::new(WIDTH, HEIGHT);
+    let image = canvas.image();
+    let mut game = new_game_with_config(canvas, ball_config_with_colors(colors)).await;
+    let ball = &game.balls()[0];
+    let radius = ball_config().radius() as u32;
+
+    // A corridor barely wider than the ball, so it bounces off one of the lines in every tick
+    paint_rect(&image, 300..1600, 530 - radius..540 - radius, RED);
+    paint_rect(&image, 300..1600, 540 + radius..550 + radius, RED);
+    ball.set_center(900.0, 540.0);
+    let dir = 80_f32.to_radians();
+    ball.set_velocity(SPEED * dir.cos(), SPEED * dir.sin());
+
+    let mut events = game.events().subscribe();
+    for _ in 0..10 {
+        game.tick().await;
+    }
+    let mut contacts = 0;
+    while let Ok(event) = events.try_recv() {
+        if matches!(event, GameEvent::Bounce { touch: Some(_), .. }) {
+            contacts += 1;
+        }
+    }
+
+    assert!(contacts > 5, "the ball only bounced {contacts} times");
+    assert_eq!(game.score().touches_left(), 1);
+}
+
+#[tokio::test]
+async fn goals_are_credited_by_last_touch() {
+    let colors = PlayerColors {
+        shared: vec![],
+        left: vec![0xff0000],
+        right: vec![0x0000ff],
+        tolerance: ColorTolerance::Exact,
+    };
+
+    for (color, own_goal) in [(RED, true), (Rgba([0, 0, 255, 255]), false)] {
+        let canvas = MemoryCanvas::new(WIDTH, HEIGHT);
+        let image = canvas.image();
+        let mut game = new_game_with_config(canvas, ball_config_with_colors(colors.clone())).await;
+        let ball = &game.balls()[0];
+
+        // A player in front of the left goal deflects the ball into it
+        paint_rect(&image, 170..180, 400..680, color);
+        ball.set_center(100.0, 540.0);
+        ball.set_velocity(SPEED, 0.0);
+
+        let mut scored = Vec::new();
+        for _ in 0..20 {
+            scored = game.tick().await;
+            if !scored.is_empty() {
+                break;
+            }
+        }
+
+        let [color_r, color_g, color_b, _] = color.0;
+        let touching_team = if own_goal { Team::Left } else { Team::Right };
+        let expected = GoalScored {
+            goal: Goal::Left,
+            scoring_team: Team::Right,
+            own_goal,
+            last_touch: Some(Touch {
+                rgb: u32::from_be_bytes([0, color_r, color_g, color_b]),
+                player: Player::Team(touching_team),
+            }),
+            assist: None,
+        };
+        assert_eq!(scored, vec![expected]);
+
+        let score = game.score();
+        assert_eq!((score.points_left(), score.points_right()), (0, 1));
+        assert_eq!(
+            (score.own_goals_left(), score.own_goals_right()),
+            (own_goal as u32, 0)
+        );
+        assert_eq!(
+            (score.touches_left(), score.touches_right()),
+            if own_goal { (1, 0) } else { (0, 1) }
+        );
+        assert_eq!(
+            game.balls()[0].last_touch(),
+            None,
+            "the kick-off clears the last touch"
+        );
+    }
+}
+
+#[tokio::test]
+async fn passes_are_credited_as_assists() {
+    let blue = Rgba([0, 0, 255, 255]);
+    let colors = PlayerColors {
+        shared: vec![],
+        left: vec![0xff0000],
+        right: vec![0x0000ff],
+        tolerance: ColorTolerance::Exact,
+    };
+    let canvas = MemoryCanvas::new(WIDTH, HEIGHT);
+    let image = canvas.image();
+    let mut game = new_game_with_config(canvas, ball_config_with_colors(colors)).await;
+    let ball = &game.balls()[0];
+
+    // A player of the right team passes the ball to a teammate, who shoots it into the left goal
+    paint_rect(&image, 200..210, 400..680, blue);
+    ball.set_center(300.0, 540.0);
+    ball.set_velocity(-SPEED, 0.0);
+    for _ in 0..20 {
+        game.tick().await;
+        if game.score().touches_right() == 1 {
+            break;
+        }
+    }
+    paint_rect(&image, 200..210, 400..680, Rgba([0, 0, 0, 255]));
+    paint_rect(&image, 400..410, 400..680, blue);
+
+    let mut scored = Vec::new();
+    for _ in 0..60 {
+        scored = game.tick().await;
+        if !scored.is_empty() {
+            break;
+        }
+    }
+
+    let pass = Touch {
+        rgb: 0x0000ff,
+        player: Player::Team(Team::Right),
+    };
+    assert_eq!(
+        scored,
+        vec![GoalScored::new(Goal::Left, Some(pass), Some(pass))]
+    );
+    let score = game.score();
+    assert_eq!((score.assists_left(), score.assists_right()), (0, 1));
+    assert_eq!(score.touches_right(), 2);
+    assert_eq!(score.last_touch(), Some(Team::Right));
+}
+
+#[tokio::test]
+async fn kick_off_publishes_events() {
+    let events = Events::new();
+    let mut received = events.subscribe();
+    Game::new(
+        MemoryCanvas::new(WIDTH, HEIGHT),
+        None,
+        field_layout(),
+        ball_config(),
+        2,
+        SEED,
+        events,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(received.try_recv(), Ok(GameEvent::Reset { ball: 0 }));
+    assert_eq!(received.try_recv(), Ok(GameEvent::Reset { ball: 1 }));
+}
+
+#[tokio::test]
+async fn goal_publishes_events() {
+    let canvas = MemoryCanvas::new(WIDTH, HEIGHT);
+    let image = canvas.image();
+    let mut game = new_game(canvas, 1).await;
+    let mut events = game.events().subscribe();
+    let ball = &game.balls()[0];
+
+    // The ball bounces off the player, straight into the left goal
+    paint_rect(&image, 170..180, 400..680, RED);
+    ball.set_center(100.0, 540.0);
+    ball.set_velocity(SPEED, 0.0);
+    for _ in 0..20 {
+        if !game.tick().await.is_empty() {
+            break;
+        }
+    }
+
+    let mut received = Vec::new();
+    while let Ok(event) = events.try_recv() {
+        received.push(event);
+    }
+    let touch = Touch {
+        rgb: 0xff0000,
+        player: Player::Anyone,
+    };
+    assert_eq!(
+        received,
+        vec![
+            GameEvent::Bounce {
+                ball: 0,
+                touch: Some(touch),
+            },
+            GameEvent::Goal {
+                ball: 0,
+                goal: GoalScored::new(Goal::Left, Some(touch), None),
+            },
+            GameEvent::Score {
+                points_left: 0,
+                points_right: 1,
+            },
+            GameEvent::Reset { ball: 0 },
+        ]
+    );
+}
@@ -0,0 +1,88 @@
+//! Parses the text based parts of the Pixelflut protocol, without any connection
+
+use pixel_soccer::protocol::{PixelflutResponse, ServerCapabilities};
+
+#[test]
+fn capabilities_are_parsed_from_help_text() {
+    let help_text = "\
+Pixelflut server, supported commands:
+HELP: Show this help
+SIZE: Get the size of the canvas
+PX x y rrggbbaa: Set the color of a pixel
+PB<x><y><rgba>: Binary set pixel
+OFFSET x y: Move all following commands
+GETRECT x y w h: Read a rectangle of pixels
+";
+    assert_eq!(
+        ServerCapabilities::from_help_text(help_text, true),
+        ServerCapabilities {
+            binary: true,
+            offset: true,
+            alpha: true,
+            pixel_reads: true,
+            get_rect: true,
+        }
+    );
+
+    // Only the basic commands, the pixel reads are tested separately
+    let help_text = "HELP\nSIZE\nPX x y rrggbb\n";
+    assert_eq!(
+        ServerCapabilities::from_help_text(help_text, false),
+        ServerCapabilities::default()
+    );
+}
+
+#[test]
+fn responses_are_parsed() {
+    assert!(matches!(
+        PixelflutResponse::parse("PX 12 34 abcdef\n"),
+        Ok(PixelflutResponse::Pixel {
+            x: 12,
+            y: 34,
+            rgb: 0xabcdef
+        })
+    ));
+    // The alpha channel is dropped
+    assert!(matches!(
+        PixelflutResponse::parse("PX 12 34 abcdef80"),
+        Ok(PixelflutResponse::Pixel { rgb: 0xabcdef, .. })
+    ));
+    assert!(matches!(
+        PixelflutResponse::parse("SIZE 1920 1080"),
+        Ok(PixelflutResponse::Size {
+            width: 1920,
+            height: 1080
+        })
+    ));
+}
+
+#[test]
+fn malformed_responses_keep_the_connection() {
+    // The name of the expected error variant for every response
+    let cases = [
+        ("PX x 34 abcdef", "MalformedCoordinate"),
+        ("PX 12 -1 abcdef", "MalformedCoordinate"),
+        ("PX 70000 34 abcdef", "MalformedCoordinate"),
+        ("PX 12", "MalformedCoordinate"),
+        ("SIZE 1920", "MalformedCoordinate"),
+        ("PX 12 34 abcdef0", "MalformedColor"),
+        ("PX 12 34 abcde", "MalformedColor"),
+        ("PX 12 34 +bcdef", "MalformedColor"),
+        ("PX 12 34 ghijkl", "MalformedColor"),
+        ("PX 12 34", "MalformedColor"),
+        ("HELLO 12 34", "UnexpectedResponse"),
+        ("", "UnexpectedResponse"),
+    ];
+
+    for (line, variant) in cases {
+        let err = PixelflutResponse::parse(line).unwrap_err();
+        assert!(
+            format!("{err:?}").starts_with(&format!("{variant}(")),
+            "expected {variant} for {line:?}, got {err:?}"
+        );
+        assert!(
+            !err.is_connection_error(),
+            "{line:?} must not break the connection"
+        );
+    }
+}
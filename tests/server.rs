@@ -0,0 +1,350 @@
+//! Plays games against the bundled Pixelflut server over TCP, the same way it runs in production
+
+mod common;
+
+use common::{ball_config, field_layout, goals, new_game, paint_rect, MAX_SPEED, RED, SPEED};
+use image::GenericImageView;
+use pixel_soccer::{
+    canvas::Canvas,
+    client::Client,
+    events::{Connection, GameEvent},
+    game::Goal,
+    protocol::{Encoding, PixelflutRequest},
+    server::Server,
+};
+use std::{io, sync::Arc, time::Duration};
+use tokio::{net::TcpListener, runtime::Runtime, time::Instant};
+
+/// How long to wait for the game to draw something. Generous, so that the tests don't fail on slow machines.
+const DRAW_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Starts a server on a random port and returns it together with its address
+async fn start_server() -> (Arc<Server>, String) {
+    let server = Arc::new(Server::new(1920, 1080));
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+
+    let serving = Arc::clone(&server);
+    tokio::spawn(async move { serving.serve(listener).await });
+
+    (server, address)
+}
+
+/// Binds a listener to `address`, retrying while the address is still in use (e.g. by a server that was just killed)
+async fn bind(address: &str) -> std::net::TcpListener {
+    let deadline = Instant::now() + DRAW_TIMEOUT;
+    loop {
+        match TcpListener::bind(address).await {
+            Ok(listener) => return listener.into_std().unwrap(),
+            Err(err) if Instant::now() >= deadline => panic!("failed to bind to {address}: {err}"),
+            Err(_) => tokio::time::sleep(Duration::from_millis(100)).await,
+        }
+    }
+}
+
+/// Starts a server on its own runtime, so that it can be killed together with all of its connections by shutting the
+/// runtime down
+fn start_killable_server(listener: std::net::TcpListener) -> Runtime {
+    let runtime = Runtime::new().unwrap();
+    runtime.spawn(async move {
+        let listener = TcpListener::from_std(listener).unwrap();
+        Server::new(1920, 1080).serve(listener).await
+    });
+    runtime
+}
+
+/// Checks `condition` every 100ms until it holds or the [`DRAW_TIMEOUT`] is reached.
+/// Returns if the condition holds in the end.
+async fn wait_until(mut condition: impl FnMut() -> bool) -> bool {
+    let deadline = Instant::now() + DRAW_TIMEOUT;
+    while Instant::now() < deadline {
+        if condition() {
+            return true;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    condition()
+}
+
+#[tokio::test]
+async fn server_speaks_pixelflut() {
+    let (_server, address) = start_server().await;
+    let mut client = Client::new(&address, None).await.unwrap();
+
+    assert_eq!(client.get_screen_size().await.unwrap(), (1920, 1080));
+    let capabilities = client.probe_capabilities().await.unwrap();
+    assert!(capabilities.pixel_reads && capabilities.binary && capabilities.offset);
+    assert!(!capabilities.get_rect);
+
+    for encoding in [Encoding::Ascii, Encoding::Binary] {
+        client
+            .write_commands(
+                &[
+                    PixelflutRequest::SetOffset { x: 100, y: 200 },
+                    PixelflutRequest::SetPixel {
+                        x: 1,
+                        y: 2,
+                        rgb: 0x123456,
+                    },
+                    PixelflutRequest::SetOffset { x: 0, y: 0 },
+                    PixelflutRequest::SetPixel {
+                        x: 5,
+                        y: 6,
+                        rgb: 0xabcdef,
+                    },
+                ],
+                encoding,
+            )
+            .await
+            .unwrap();
+
+        let rect = client
+            .get_screen_rect(100, 201, 2, 2, 1920, 1080)
+            .await
+            .unwrap();
+        assert_eq!(rect[1][1], 0x123456, "{encoding:?}");
+        let rect = client
+            .get_screen_rect(5, 6, 1, 1, 1920, 1080)
+            .await
+            .unwrap();
+        assert_eq!(rect[0][0], 0xabcdef, "{encoding:?}");
+
+        // Overwrite the pixels, so the next encoding is tested for real
+        client
+            .write_bytes(b"PX 101 202 000000\nPX 5 6 000000\n")
+            .await
+            .unwrap();
+    }
+}
+
+#[tokio::test]
+async fn game_over_tcp_scores_goal() {
+    let (_server, address) = start_server().await;
+    let mut game = new_game(Client::new(&address, None).await.unwrap(), 1).await;
+    let ball = &game.balls()[0];
+    let kick_off = ball.center();
+
+    ball.set_center(45.0, 540.0);
+    ball.set_velocity(-SPEED, 0.0);
+
+    assert_eq!(goals(game.tick().await), vec![Goal::Left]);
+    assert_eq!(game.score().points_right(), 1);
+    assert_eq!(game.balls()[0].center(), kick_off);
+}
+
+#[tokio::test]
+async fn game_over_tcp_bounces_off_player() {
+    let (server, address) = start_server().await;
+    let mut game = new_game(Client::new(&address, None).await.unwrap(), 1).await;
+    let ball = &game.balls()[0];
+
+    paint_rect(&server.canvas(), 1000..1010, 300..500, RED);
+    ball.set_center(900.0, 400.0);
+    ball.set_velocity(SPEED, 0.0);
+
+    for _ in 0..10 {
+        game.tick().await;
+    }
+
+    let ball = &game.balls()[0];
+    assert!(
+        ball.velocity().0 < 0.0,
+        "ball should move left after the bounce"
+    );
+    assert!(
+        ball.center().0 < 1000.0 - 40.0,
+        "ball must not enter the player"
+    );
+}
+
+#[tokio::test]
+async fn game_draws_field_onto_server() {
+    let (server, address) = start_server().await;
+    let game = new_game(Client::new(&address, None).await.unwrap(), 1).await;
+
+    let field = image::open("images/field_v3.png").unwrap();
+    let canvas = server.canvas();
+    let (mut drawn, mut total) = (0, 0);
+    let field_drawn = || {
+        let canvas = canvas.lock().unwrap();
+        (drawn, total) = (0, 0);
+        for (x, y, pixel) in field.pixels() {
+            if pixel.0[3] == 255 {
+                total += 1;
+                if *canvas.get_pixel(x, y) == pixel {
+                    drawn += 1;
+                }
+            }
+        }
+        // The ball and score might be drawn on top of the field
+        drawn * 100 > total * 95
+    };
+
+    // The game runs forever, we only need it to draw the field once
+    let field_drawn = tokio::select! {
+        result = game.start(20) => panic!("the game stopped: {result:?}"),
+        field_drawn = wait_until(field_drawn) => field_drawn,
+    };
+    assert!(
+        field_drawn,
+        "only {drawn} of {total} field pixels were drawn"
+    );
+}
+
+#[tokio::test]
+async fn game_stays_in_viewport() {
+    let (server, address) = start_server().await;
+    // In the middle of the screen, so that there is something around it on every side
+    let viewport = "480,270,960,540".parse().unwrap();
+    let client = Client::new(&address, Some(viewport)).await.unwrap();
+    let mut game = new_game(client, 1).await;
+    let ball = &game.balls()[0];
+    assert_eq!(ball.center(), (480.0, 270.0));
+
+    // A player in the viewport, and another one where the player would be without translating the coordinates
+    paint_rect(&server.canvas(), 980..990, 470..610, RED);
+    paint_rect(&server.canvas(), 430..440, 200..340, RED);
+    // The field is scaled to half its size, and so is the ball
+    let radius = ball_config().radius() / 2.0;
+    ball.set_center(400.0, 270.0);
+    ball.set_velocity(SPEED, 0.0);
+
+    for _ in 0..12 {
+        game.tick().await;
+    }
+
+    let ball = &game.balls()[0];
+    assert!(
+        ball.velocity().0 < 0.0,
+        "ball should move left after the bounce"
+    );
+    assert!(
+        ball.center().0 > 420.0 && ball.center().0 < 500.0 - radius,
+        "ball must bounce off the player in the viewport, but is at {:?}",
+        ball.center()
+    );
+
+    // A ball moving towards the right edge must not leave the viewport, not even for one tick
+    // At half size the ball moves in two sub-steps of 5 pixels, the second one starts just before the edge
+    ball.set_center(960.0 - radius - 6.0, 100.0);
+    ball.set_velocity(MAX_SPEED, 0.0);
+    game.tick().await;
+    let ball = &game.balls()[0];
+    assert!(
+        ball.center().0 <= 960.0 - radius,
+        "ball must stay in the viewport, but is at {:?}",
+        ball.center()
+    );
+
+    // Once the field is drawn into the viewport, everything has been drawn at least once
+    let field = field_layout().fit_to_screen(960, 540).image;
+    let canvas = server.canvas();
+    let field_drawn = || {
+        let canvas = canvas.lock().unwrap();
+        let (mut drawn, mut total) = (0, 0);
+        for (x, y, pixel) in field.pixels() {
+            if pixel.0[3] == 255 {
+                total += 1;
+                if *canvas.get_pixel(480 + x, 270 + y) == pixel {
+                    drawn += 1;
+                }
+            }
+        }
+        drawn * 100 > total * 95
+    };
+    let field_drawn = tokio::select! {
+        result = game.start(20) => panic!("the game stopped: {result:?}"),
+        field_drawn = wait_until(field_drawn) => field_drawn,
+    };
+    assert!(field_drawn, "the field was not drawn into the viewport");
+
+    let canvas = canvas.lock().unwrap();
+    let drawn_outside = canvas
+        .enumerate_pixels()
+        .filter(|(x, y, pixel)| {
+            let inside = (480..1440).contains(x) && (270..810).contains(y);
+            !inside && **pixel != RED && pixel.0 != [0, 0, 0, 255]
+        })
+        .count();
+    assert_eq!(
+        drawn_outside, 0,
+        "nothing must be drawn outside the viewport"
+    );
+}
+
+#[tokio::test]
+async fn viewport_must_fit_into_screen() {
+    let (_server, address) = start_server().await;
+    let viewport = "1000,0,1000,100".parse().unwrap();
+    let mut client = Client::new(&address, Some(viewport)).await.unwrap();
+    let err = io::Error::from(client.get_screen_size().await.unwrap_err());
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+}
+
+#[tokio::test]
+async fn game_reconnects_after_server_restart() {
+    let listener = bind("127.0.0.1:0").await;
+    let address = listener.local_addr().unwrap().to_string();
+    let server = start_killable_server(listener);
+    let mut game = new_game(Client::new(&address, None).await.unwrap(), 1).await;
+    let mut events = game.events().subscribe();
+    game.tick().await;
+
+    server.shutdown_background();
+    let restart_address = address.clone();
+    let restarted = tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        start_killable_server(bind(&restart_address).await)
+    });
+
+    // Responses that were already received can hide the broken connection for a tick. Once the game notices, it
+    // reconnects as soon as the server is back.
+    let mut received = Vec::new();
+    for _ in 0..10 {
+        game.tick().await;
+        received.extend(std::iter::from_fn(|| events.try_recv().ok()));
+        if received.contains(&GameEvent::Reconnected {
+            connection: Connection::Ball(0),
+        }) {
+            break;
+        }
+    }
+    let server = restarted.await.unwrap();
+    assert!(
+        received.iter().any(|event| matches!(
+            event,
+            GameEvent::ConnectionError {
+                connection: Connection::Ball(0),
+                reconnecting: true,
+                ..
+            }
+        )),
+        "the broken connection must be reported: {received:?}"
+    );
+    assert!(
+        received.contains(&GameEvent::Reconnected {
+            connection: Connection::Ball(0)
+        }),
+        "the ball must reconnect: {received:?}"
+    );
+
+    // The ball only moves if its pixel reads succeed
+    let before = game.balls()[0].center();
+    for _ in 0..5 {
+        game.tick().await;
+    }
+    assert_ne!(
+        game.balls()[0].center(),
+        before,
+        "the ball must keep moving"
+    );
+    let errors: Vec<_> = std::iter::from_fn(|| events.try_recv().ok())
+        .filter(|event| matches!(event, GameEvent::ConnectionError { .. }))
+        .collect();
+    assert!(
+        errors.is_empty(),
+        "the new connection must work: {errors:?}"
+    );
+
+    server.shutdown_background();
+}